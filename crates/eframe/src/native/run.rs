@@ -958,11 +958,14 @@ mod wgpu_integration {
         fn set_window(
             &mut self,
             window: winit::window::Window,
-        ) -> std::result::Result<(), wgpu::RequestDeviceError> {
+        ) -> std::result::Result<(), egui_wgpu::PainterError> {
+            let window_id = window.id();
             self.window = Some(window);
             if let Some(running) = &mut self.running {
                 unsafe {
-                    pollster::block_on(running.painter.set_window(self.window.as_ref()))?;
+                    pollster::block_on(
+                        running.painter.set_window(window_id, self.window.as_ref()),
+                    )?;
                 }
             }
             Ok(())
@@ -970,11 +973,10 @@ mod wgpu_integration {
 
         #[allow(unsafe_code)]
         #[cfg(target_os = "android")]
-        fn drop_window(&mut self) -> std::result::Result<(), wgpu::RequestDeviceError> {
-            self.window = None;
-            if let Some(running) = &mut self.running {
+        fn drop_window(&mut self) -> std::result::Result<(), egui_wgpu::PainterError> {
+            if let (Some(running), Some(window)) = (&mut self.running, self.window.take()) {
                 unsafe {
-                    pollster::block_on(running.painter.set_window(None))?;
+                    pollster::block_on(running.painter.set_window(window.id(), None))?;
                 }
             }
             Ok(())
@@ -985,7 +987,7 @@ mod wgpu_integration {
             event_loop: &EventLoopWindowTarget<UserEvent>,
             storage: Option<Box<dyn epi::Storage>>,
             window: winit::window::Window,
-        ) -> std::result::Result<(), wgpu::RequestDeviceError> {
+        ) -> std::result::Result<(), egui_wgpu::PainterError> {
             #[allow(unsafe_code, unused_mut, unused_unsafe)]
             let painter = unsafe {
                 let mut painter = egui_wgpu::winit::Painter::new(
@@ -993,7 +995,7 @@ mod wgpu_integration {
                     self.native_options.multisampling.max(1) as _,
                     self.native_options.depth_buffer,
                 );
-                pollster::block_on(painter.set_window(Some(&window)))?;
+                pollster::block_on(painter.set_window(window.id(), Some(&window)))?;
                 painter
             };
 
@@ -1111,12 +1113,22 @@ mod wgpu_integration {
                     integration.egui_ctx.tessellate(shapes)
                 };
 
-                painter.paint_and_update_textures(
-                    integration.egui_ctx.pixels_per_point(),
-                    app.clear_color(&integration.egui_ctx.style().visuals),
-                    &clipped_primitives,
-                    &textures_delta,
-                );
+                let paint_result = painter
+                    .set_pixels_per_point(window.id(), integration.egui_ctx.pixels_per_point())
+                    .and_then(|()| {
+                        painter.paint_and_update_textures(
+                            window.id(),
+                            Some(app.clear_color(&integration.egui_ctx.style().visuals)),
+                            &clipped_primitives,
+                            std::slice::from_ref(&textures_delta),
+                        )
+                    });
+                match paint_result {
+                    Ok(_) | Err(egui_wgpu::PainterError::SurfaceError(_)) => {
+                        // Surface errors are reported by `WgpuConfiguration::on_surface_error`.
+                    }
+                    Err(err) => tracing::error!("Failed to paint: {}", err),
+                }
 
                 integration.post_rendering(app.as_mut(), window);
                 integration.post_present(window);
@@ -1191,7 +1203,7 @@ mod wgpu_integration {
                     EventResult::Wait
                 }
 
-                winit::event::Event::WindowEvent { event, .. } => {
+                winit::event::Event::WindowEvent { event, window_id } => {
                     if let Some(running) = &mut self.running {
                         // On Windows, if a window is resized by the user, it should repaint synchronously, inside the
                         // event handler.
@@ -1219,10 +1231,13 @@ mod wgpu_integration {
                                 // See: https://github.com/rust-windowing/winit/issues/208
                                 // This solves an issue where the app would panic when minimizing on Windows.
                                 if physical_size.width > 0 && physical_size.height > 0 {
-                                    running.painter.on_window_resized(
+                                    if let Err(err) = running.painter.on_window_resized(
+                                        *window_id,
                                         physical_size.width,
                                         physical_size.height,
-                                    );
+                                    ) {
+                                        tracing::error!("Failed to resize window: {}", err);
+                                    }
                                 }
                             }
                             winit::event::WindowEvent::ScaleFactorChanged {
@@ -1230,9 +1245,13 @@ mod wgpu_integration {
                                 ..
                             } => {
                                 repaint_asap = true;
-                                running
-                                    .painter
-                                    .on_window_resized(new_inner_size.width, new_inner_size.height);
+                                if let Err(err) = running.painter.on_window_resized(
+                                    *window_id,
+                                    new_inner_size.width,
+                                    new_inner_size.height,
+                                ) {
+                                    tracing::error!("Failed to resize window: {}", err);
+                                }
                             }
                             winit::event::WindowEvent::CloseRequested
                                 if running.integration.should_close() =>
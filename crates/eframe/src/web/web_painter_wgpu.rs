@@ -84,8 +84,19 @@ impl WebPainterWgpu {
             egui_wgpu::preferred_framebuffer_format(&surface.get_supported_formats(&adapter));
 
         let depth_format = options.wgpu_options.depth_format;
-        let renderer = egui_wgpu::Renderer::new(&device, target_format, depth_format, 1);
+        let renderer = egui_wgpu::Renderer::new(
+            &device,
+            &egui_wgpu::RendererDescriptor {
+                output_color_format: target_format,
+                output_depth_format: depth_format,
+                msaa_samples: 1,
+                texture_bind_group_layout: None,
+                screen_uniform_binding: 0,
+                label_prefix: String::new(),
+            },
+        );
         let render_state = RenderState {
+            max_texture_side: device.limits().max_texture_dimension_2d as usize,
             device: Arc::new(device),
             queue: Arc::new(queue),
             target_format,
@@ -159,21 +170,25 @@ impl WebPainter for WebPainterWgpu {
         let user_cmd_bufs = {
             let mut renderer = render_state.renderer.write();
             for (id, image_delta) in &textures_delta.set {
-                renderer.update_texture(
+                if let Err(err) = renderer.update_texture(
                     &render_state.device,
                     &render_state.queue,
                     *id,
                     image_delta,
-                );
+                ) {
+                    tracing::error!("Failed to update texture {:?}: {}", id, err);
+                }
             }
 
-            renderer.update_buffers(
-                &render_state.device,
-                &render_state.queue,
-                &mut encoder,
-                clipped_primitives,
-                &screen_descriptor,
-            )
+            renderer
+                .update_buffers(
+                    &render_state.device,
+                    &render_state.queue,
+                    &mut encoder,
+                    clipped_primitives,
+                    &screen_descriptor,
+                )
+                .command_buffers
         };
 
         // Resize surface if needed
@@ -248,13 +263,6 @@ impl WebPainter for WebPainterWgpu {
             Some(frame)
         };
 
-        {
-            let mut renderer = render_state.renderer.write();
-            for id in &textures_delta.free {
-                renderer.free_texture(id);
-            }
-        }
-
         // Submit the commands: both the main buffer and user-defined ones.
         render_state.queue.submit(
             user_cmd_bufs
@@ -262,6 +270,14 @@ impl WebPainter for WebPainterWgpu {
                 .chain(std::iter::once(encoder.finish())),
         );
 
+        // Free the textures only once the frame that may still use them has been submitted.
+        {
+            let mut renderer = render_state.renderer.write();
+            for id in &textures_delta.free {
+                renderer.free_texture(id);
+            }
+        }
+
         if let Some(frame) = frame {
             frame.present();
         }
@@ -0,0 +1,195 @@
+use std::sync::{
+    atomic::{AtomicU8, Ordering},
+    Arc,
+};
+
+use crate::RenderStageTimings;
+
+/// Timestamps written each frame:
+/// start of `update_buffers`, start of `render` and end of `render`.
+const TIMESTAMPS_PER_FRAME: u32 = 3;
+
+/// Number of frames that can be in flight at the same time.
+///
+/// The results of a frame are read back once the GPU is done with it,
+/// which is typically a couple of frames later, so that we never stall waiting for the GPU.
+const NUM_SLOTS: usize = 3;
+
+const READBACK_BUFFER_SIZE: wgpu::BufferAddress =
+    TIMESTAMPS_PER_FRAME as wgpu::BufferAddress * std::mem::size_of::<u64>() as wgpu::BufferAddress;
+
+enum SlotState {
+    /// Ready to be used for a new frame.
+    Free,
+
+    /// Timestamps have been recorded into the command encoder, but not yet submitted.
+    Recorded,
+
+    /// Waiting for the readback buffer to be mapped. The state is one of the `MAPPING_*`
+    /// constants, and is updated by the `map_async` callback.
+    Mapping(Arc<AtomicU8>),
+}
+
+const MAPPING_PENDING: u8 = 0;
+const MAPPING_DONE: u8 = 1;
+const MAPPING_FAILED: u8 = 2;
+
+struct Slot {
+    readback_buffer: wgpu::Buffer,
+    state: SlotState,
+}
+
+/// Measures the GPU time spent in the render stages of egui using timestamp queries.
+///
+/// Requires [`wgpu::Features::TIMESTAMP_QUERY`].
+pub(crate) struct GpuTimer {
+    query_set: wgpu::QuerySet,
+    slots: Vec<Slot>,
+    next_slot: usize,
+
+    /// Nanoseconds per timestamp tick.
+    timestamp_period: f32,
+
+    latest: Option<RenderStageTimings>,
+}
+
+/// The slot used for the timestamps of the current frame.
+#[derive(Clone, Copy)]
+pub(crate) struct FrameQueries(usize);
+
+impl GpuTimer {
+    /// Returns `None` if the device does not support timestamp queries.
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Option<Self> {
+        if !device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+            return None;
+        }
+
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("egui_timestamp_queries"),
+            ty: wgpu::QueryType::Timestamp,
+            count: TIMESTAMPS_PER_FRAME * NUM_SLOTS as u32,
+        });
+
+        let slots = (0..NUM_SLOTS)
+            .map(|_| Slot {
+                readback_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("egui_timestamp_readback_buffer"),
+                    size: READBACK_BUFFER_SIZE,
+                    usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+                    mapped_at_creation: false,
+                }),
+                state: SlotState::Free,
+            })
+            .collect();
+
+        Some(Self {
+            query_set,
+            slots,
+            next_slot: 0,
+            timestamp_period: queue.get_timestamp_period(),
+            latest: None,
+        })
+    }
+
+    /// The timings of the most recent frame whose results have been read back.
+    pub fn latest(&self) -> Option<RenderStageTimings> {
+        self.latest
+    }
+
+    /// Read back the results of finished frames, and reserve queries for a new frame.
+    ///
+    /// Returns `None` if all slots are still in use by the GPU,
+    /// in which case this frame is simply not timed.
+    pub fn begin_frame(&mut self) -> Option<FrameQueries> {
+        for slot in &mut self.slots {
+            if let SlotState::Mapping(mapping) = &slot.state {
+                let mapping = mapping.load(Ordering::Acquire);
+                if mapping == MAPPING_FAILED {
+                    // Nothing to read, but the slot can be used again.
+                    slot.state = SlotState::Free;
+                } else if mapping == MAPPING_DONE {
+                    {
+                        let data = slot.readback_buffer.slice(..).get_mapped_range();
+                        let timestamps: &[u64] = bytemuck::cast_slice(&data);
+                        let ticks_to_ns =
+                            |ticks: u64| (ticks as f64 * self.timestamp_period as f64) as u64;
+                        self.latest = Some(RenderStageTimings {
+                            update_buffers_gpu_ns: ticks_to_ns(
+                                timestamps[1].saturating_sub(timestamps[0]),
+                            ),
+                            render_gpu_ns: ticks_to_ns(timestamps[2].saturating_sub(timestamps[1])),
+                        });
+                    }
+                    slot.readback_buffer.unmap();
+                    slot.state = SlotState::Free;
+                }
+            }
+        }
+
+        let index = self.next_slot;
+        if matches!(self.slots[index].state, SlotState::Free) {
+            self.next_slot = (index + 1) % NUM_SLOTS;
+            Some(FrameQueries(index))
+        } else {
+            None
+        }
+    }
+
+    /// Write timestamp number `timestamp` (`0..3`) of the given frame.
+    pub fn write_timestamp(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        queries: FrameQueries,
+        timestamp: u32,
+    ) {
+        debug_assert!(timestamp < TIMESTAMPS_PER_FRAME);
+        encoder.write_timestamp(
+            &self.query_set,
+            queries.0 as u32 * TIMESTAMPS_PER_FRAME + timestamp,
+        );
+    }
+
+    /// Resolve the timestamps of the given frame into its readback buffer.
+    ///
+    /// Call after the last timestamp has been written.
+    pub fn resolve(&mut self, encoder: &mut wgpu::CommandEncoder, queries: FrameQueries) {
+        let first = queries.0 as u32 * TIMESTAMPS_PER_FRAME;
+        let slot = &mut self.slots[queries.0];
+        encoder.resolve_query_set(
+            &self.query_set,
+            first..first + TIMESTAMPS_PER_FRAME,
+            &slot.readback_buffer,
+            0,
+        );
+        slot.state = SlotState::Recorded;
+    }
+
+    /// Start mapping the readback buffers of the frames that were just submitted.
+    ///
+    /// Call right after the command buffer containing the timestamps has been submitted.
+    pub fn after_submit(&mut self, device: &wgpu::Device) {
+        for slot in &mut self.slots {
+            if matches!(slot.state, SlotState::Recorded) {
+                let mapping = Arc::new(AtomicU8::new(MAPPING_PENDING));
+                let on_mapped = {
+                    let mapping = mapping.clone();
+                    move |result: Result<(), wgpu::BufferAsyncError>| {
+                        if let Err(err) = result {
+                            tracing::warn!("Failed to read back GPU timestamps: {}", err);
+                            mapping.store(MAPPING_FAILED, Ordering::Release);
+                        } else {
+                            mapping.store(MAPPING_DONE, Ordering::Release);
+                        }
+                    }
+                };
+                slot.readback_buffer
+                    .slice(..)
+                    .map_async(wgpu::MapMode::Read, on_mapped);
+                slot.state = SlotState::Mapping(mapping);
+            }
+        }
+
+        // Make progress on the mapping without blocking:
+        device.poll(wgpu::Maintain::Poll);
+    }
+}
@@ -11,26 +11,160 @@ pub use wgpu;
 /// Low-level painting of [`egui`](https://github.com/emilk/egui) on [`wgpu`].
 pub mod renderer;
 pub use renderer::CallbackFn;
+pub use renderer::GeometryError;
+pub use renderer::RawMesh;
 pub use renderer::Renderer;
+pub use renderer::RendererDescriptor;
+pub use renderer::RendererError;
+pub use renderer::TextureError;
+pub use renderer::UpdateBuffersResult;
 
 /// Module for painting [`egui`](https://github.com/emilk/egui) with [`wgpu`] on [`winit`].
 #[cfg(feature = "winit")]
 pub mod winit;
 
+#[cfg(feature = "winit")]
+mod gpu_timer;
+
+#[cfg(not(target_arch = "wasm32"))]
+mod readback;
+
 use std::sync::Arc;
 
 use epaint::mutex::RwLock;
 
 /// Access to the render state for egui.
+///
+/// Cloning a [`RenderState`] is cheap, and the clone shares the same device, queue and
+/// [`Renderer`] as the original (see [`Self::clone_renderer`]).
 #[derive(Clone)]
 pub struct RenderState {
     pub device: Arc<wgpu::Device>,
     pub queue: Arc<wgpu::Queue>,
     pub target_format: wgpu::TextureFormat,
     pub renderer: Arc<RwLock<Renderer>>,
+
+    /// The maximum texture dimension supported by [`Self::device`],
+    /// i.e. its [`wgpu::Limits::max_texture_dimension_2d`].
+    pub max_texture_side: usize,
+}
+
+impl RenderState {
+    /// The device used by egui, e.g. for creating buffers or running compute passes
+    /// from a [`CallbackFn`].
+    pub fn device(&self) -> &Arc<wgpu::Device> {
+        &self.device
+    }
+
+    /// The queue used by egui.
+    pub fn queue(&self) -> &Arc<wgpu::Queue> {
+        &self.queue
+    }
+
+    /// Get another handle to the [`Renderer`] of this render state.
+    ///
+    /// This does _not_ create a new renderer: the returned handle refers to the same
+    /// [`Renderer`], so e.g. textures registered through one handle are visible through
+    /// all others (including clones of this [`RenderState`]).
+    pub fn clone_renderer(&self) -> Arc<RwLock<Renderer>> {
+        Arc::clone(&self.renderer)
+    }
+}
+
+impl std::fmt::Debug for RenderState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RenderState")
+            .field("device", &self.device)
+            .field("queue", &self.queue)
+            .field("target_format", &self.target_format)
+            .field("max_texture_side", &self.max_texture_side)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Statistics about a painted frame.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FrameStats {
+    /// GPU time spent in the different render stages.
+    ///
+    /// This is only available if the device was created with [`wgpu::Features::TIMESTAMP_QUERY`]
+    /// (see [`WgpuConfiguration::device_descriptor`]).
+    /// The timings are read back asynchronously to avoid stalling the GPU,
+    /// so they lag a couple of frames behind.
+    pub render_stage_timings: Option<RenderStageTimings>,
+
+    /// Bytes of vertex data uploaded to the GPU.
+    ///
+    /// Zero if the geometry was unchanged since the previous frame.
+    pub vertex_bytes: u64,
+
+    /// Bytes of index data uploaded to the GPU.
+    ///
+    /// Zero if the geometry was unchanged since the previous frame.
+    pub index_bytes: u64,
+
+    /// Bytes of texel data uploaded to the GPU.
+    pub texture_bytes: u64,
+
+    /// Allocated size of the vertex buffer of the [`Renderer`] in bytes.
+    ///
+    /// Unlike [`Self::vertex_bytes`] this is the GPU memory held, not the data uploaded this
+    /// frame. It grows to fit the largest frame painted so far.
+    pub vertex_buffer_bytes: u64,
+
+    /// Allocated size of the index buffer of the [`Renderer`] in bytes.
+    ///
+    /// Like [`Self::vertex_buffer_bytes`], this grows to fit the largest frame painted so far.
+    pub index_buffer_bytes: u64,
+
+    /// Size of the uniform buffer of the [`Renderer`] in bytes,
+    /// or 0 if it passes the screen size in push constants instead.
+    pub uniform_buffer_bytes: u64,
+}
+
+/// Adds up the stats of several frames, e.g. of all windows painted in one application frame:
+/// `total_stats += window_stats`.
+///
+/// The buffer sizes are the largest of the two rather than the sum, since all windows of a
+/// painter share the buffers of one [`Renderer`].
+impl std::ops::AddAssign for FrameStats {
+    fn add_assign(&mut self, other: Self) {
+        self.render_stage_timings = match (self.render_stage_timings, other.render_stage_timings) {
+            (Some(mut timings), Some(other_timings)) => {
+                timings += other_timings;
+                Some(timings)
+            }
+            (timings, other_timings) => timings.or(other_timings),
+        };
+        self.vertex_bytes += other.vertex_bytes;
+        self.index_bytes += other.index_bytes;
+        self.texture_bytes += other.texture_bytes;
+        self.vertex_buffer_bytes = self.vertex_buffer_bytes.max(other.vertex_buffer_bytes);
+        self.index_buffer_bytes = self.index_buffer_bytes.max(other.index_buffer_bytes);
+        self.uniform_buffer_bytes = self.uniform_buffer_bytes.max(other.uniform_buffer_bytes);
+    }
+}
+
+/// GPU time spent in the render stages of a frame, in nanoseconds.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RenderStageTimings {
+    /// GPU time of the commands recorded while updating the buffers,
+    /// i.e. by the `prepare` stage of the paint callbacks.
+    pub update_buffers_gpu_ns: u64,
+
+    /// GPU time of the egui render pass.
+    pub render_gpu_ns: u64,
+}
+
+impl std::ops::AddAssign for RenderStageTimings {
+    fn add_assign(&mut self, other: Self) {
+        self.update_buffers_gpu_ns += other.update_buffers_gpu_ns;
+        self.render_gpu_ns += other.render_gpu_ns;
+    }
 }
 
 /// Specifies which action should be taken as consequence of a [`wgpu::SurfaceError`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum SurfaceErrorAction {
     /// Do nothing and skip the current frame.
     SkipFrame,
@@ -39,6 +173,162 @@ pub enum SurfaceErrorAction {
     RecreateSurface,
 }
 
+/// An error from the egui-wgpu painter.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PainterError {
+    /// The painter has no render state yet, or no surface for the given window
+    /// (see `winit::Painter::set_window`).
+    NotInitialized,
+
+    /// No adapter compatible with the surface and the [`WgpuConfiguration`] was found.
+    NoSuitableAdapter,
+
+    /// The adapter failed to provide a device matching [`WgpuConfiguration::device_descriptor`].
+    RequestDevice(wgpu::RequestDeviceError),
+
+    /// The device has been lost, and the painter must be recreated.
+    ///
+    /// Returned by the painting methods once `winit::Painter::is_device_lost` is set.
+    DeviceLost,
+
+    /// The frame was skipped because acquiring the surface texture failed.
+    ///
+    /// The error has already been passed to [`WgpuConfiguration::on_surface_error`].
+    SurfaceError(wgpu::SurfaceError),
+
+    /// A texture is larger than the maximum texture size of the device.
+    TextureTooLarge {
+        /// The maximum supported width and height.
+        max: u32,
+
+        /// The requested width and height.
+        requested: (u32, u32),
+    },
+
+    /// A partial texture update extends outside of the texture.
+    TextureSubUpdateOutOfBounds {
+        /// The position of the update in the texture.
+        pos: (u32, u32),
+
+        /// The width and height of the update.
+        size: (u32, u32),
+
+        /// The width and height of the texture.
+        texture_size: (u32, u32),
+    },
+
+    /// A partial texture update for a texture that has not been allocated.
+    TextureNotAllocated {
+        /// The id of the texture.
+        id: epaint::TextureId,
+    },
+
+    /// The texture format can't be used for what it was requested for,
+    /// e.g. a depth format that does not match the render pipeline.
+    UnsupportedFormat(wgpu::TextureFormat),
+
+    /// The shader module or the render pipeline of the renderer failed validation
+    /// (see [`Renderer::try_new`]).
+    ShaderCompilationFailed(String),
+
+    /// A `pixels_per_point` that is zero, negative or not finite.
+    InvalidPixelsPerPoint(f32),
+
+    /// Reading back a texture from the GPU failed.
+    Readback(wgpu::BufferAsyncError),
+
+    /// The adapter does not support these features, which were requested in
+    /// [`WgpuConfiguration::device_descriptor`].
+    FeatureNotSupported(wgpu::Features),
+}
+
+impl std::fmt::Display for PainterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotInitialized => write!(f, "the painter has not been initialized"),
+            Self::NoSuitableAdapter => write!(f, "no suitable GPU adapter found"),
+            Self::RequestDevice(err) => write!(f, "failed to request a device: {}", err),
+            Self::DeviceLost => write!(f, "the device was lost"),
+            Self::SurfaceError(err) => write!(f, "surface error: {}", err),
+            Self::TextureTooLarge { max, requested } => write!(
+                f,
+                "texture of size {}x{} is larger than the maximum texture size of {}",
+                requested.0, requested.1, max
+            ),
+            Self::TextureSubUpdateOutOfBounds {
+                pos,
+                size,
+                texture_size,
+            } => write!(
+                f,
+                "texture update of size {}x{} at {},{} is outside of the texture of size {}x{}",
+                size.0, size.1, pos.0, pos.1, texture_size.0, texture_size.1
+            ),
+            Self::TextureNotAllocated { id } => {
+                write!(f, "partial update of unallocated texture {:?}", id)
+            }
+            Self::UnsupportedFormat(format) => write!(f, "unsupported texture format {:?}", format),
+            Self::ShaderCompilationFailed(err) => write!(f, "shader compilation failed: {}", err),
+            Self::InvalidPixelsPerPoint(pixels_per_point) => {
+                write!(f, "invalid pixels_per_point: {}", pixels_per_point)
+            }
+            Self::Readback(err) => write!(f, "failed to read back a texture: {}", err),
+            Self::FeatureNotSupported(features) => {
+                write!(
+                    f,
+                    "the adapter does not support the features {:?}",
+                    features
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for PainterError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::RequestDevice(err) => Some(err),
+            Self::SurfaceError(err) => Some(err),
+            Self::Readback(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<wgpu::RequestDeviceError> for PainterError {
+    fn from(err: wgpu::RequestDeviceError) -> Self {
+        Self::RequestDevice(err)
+    }
+}
+
+impl From<renderer::RendererError> for PainterError {
+    fn from(err: renderer::RendererError) -> Self {
+        match err {
+            renderer::RendererError::InvalidPipeline(err) => Self::ShaderCompilationFailed(err),
+        }
+    }
+}
+
+impl From<renderer::TextureError> for PainterError {
+    fn from(err: renderer::TextureError) -> Self {
+        match err {
+            renderer::TextureError::TooLarge { max, requested } => {
+                Self::TextureTooLarge { max, requested }
+            }
+            renderer::TextureError::SubUpdateOutOfBounds {
+                pos,
+                size,
+                texture_size,
+            } => Self::TextureSubUpdateOutOfBounds {
+                pos,
+                size,
+                texture_size,
+            },
+            renderer::TextureError::NotAllocated { id } => Self::TextureNotAllocated { id },
+        }
+    }
+}
+
 /// Configuration for using wgpu with eframe or the egui-wgpu winit feature.
 #[derive(Clone)]
 pub struct WgpuConfiguration {
@@ -60,6 +350,18 @@ pub struct WgpuConfiguration {
     pub depth_format: Option<wgpu::TextureFormat>,
 }
 
+impl std::fmt::Debug for WgpuConfiguration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WgpuConfiguration")
+            .field("device_descriptor", &self.device_descriptor)
+            .field("backends", &self.backends)
+            .field("present_mode", &self.present_mode)
+            .field("power_preference", &self.power_preference)
+            .field("depth_format", &self.depth_format)
+            .finish_non_exhaustive()
+    }
+}
+
 impl Default for WgpuConfiguration {
     fn default() -> Self {
         Self {
@@ -121,3 +423,81 @@ macro_rules! profile_scope {
     };
 }
 pub(crate) use profile_scope;
+
+#[test]
+fn frame_stats_add_up() {
+    let mut total = FrameStats {
+        vertex_bytes: 1,
+        index_bytes: 2,
+        texture_bytes: 3,
+        ..Default::default()
+    };
+    let timings = RenderStageTimings {
+        update_buffers_gpu_ns: 10,
+        render_gpu_ns: 20,
+    };
+    let window = FrameStats {
+        render_stage_timings: Some(timings),
+        vertex_bytes: 4,
+        index_bytes: 5,
+        texture_bytes: 6,
+        vertex_buffer_bytes: 1024,
+        ..Default::default()
+    };
+
+    total += window;
+    assert_eq!(total.render_stage_timings, Some(timings));
+    assert_eq!(
+        (total.vertex_bytes, total.index_bytes, total.texture_bytes),
+        (5, 7, 9)
+    );
+    assert_eq!(total.vertex_buffer_bytes, 1024);
+
+    total += window;
+    assert_eq!(
+        total.render_stage_timings,
+        Some(RenderStageTimings {
+            update_buffers_gpu_ns: 20,
+            render_gpu_ns: 40,
+        })
+    );
+}
+
+/// Resolves a future that is ready right away, such as those of native wgpu.
+#[cfg(test)]
+fn block_on_ready<F: std::future::Future>(future: F) -> F::Output {
+    use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+    fn raw_waker() -> RawWaker {
+        fn no_op(_: *const ()) {}
+        static VTABLE: RawWakerVTable = RawWakerVTable::new(|_| raw_waker(), no_op, no_op, no_op);
+        RawWaker::new(std::ptr::null(), &VTABLE)
+    }
+
+    // SAFETY: the waker does nothing, so its data pointer is never used.
+    let waker = unsafe { Waker::from_raw(raw_waker()) };
+    let mut future = Box::pin(future);
+    match future.as_mut().poll(&mut Context::from_waker(&waker)) {
+        Poll::Ready(output) => output,
+        Poll::Pending => panic!("The future should be ready right away"),
+    }
+}
+
+#[cfg(test)]
+type TestDevice = (
+    Arc<wgpu::Instance>,
+    wgpu::Adapter,
+    Arc<wgpu::Device>,
+    Arc<wgpu::Queue>,
+);
+
+/// A device of the default adapter, or `None` if there is no adapter to test with.
+#[cfg(test)]
+fn test_device() -> Option<TestDevice> {
+    let instance = Arc::new(wgpu::Instance::new(wgpu::Backends::all()));
+    let adapter =
+        block_on_ready(instance.request_adapter(&wgpu::RequestAdapterOptions::default()))?;
+    let (device, queue) =
+        block_on_ready(adapter.request_device(&wgpu::DeviceDescriptor::default(), None)).unwrap();
+    Some((instance, adapter, Arc::new(device), Arc::new(queue)))
+}
@@ -0,0 +1,145 @@
+use std::num::NonZeroU32;
+
+/// Whether [`read_texture`] can read back textures of the given format.
+pub(crate) fn is_readable_format(format: wgpu::TextureFormat) -> bool {
+    matches!(
+        format,
+        wgpu::TextureFormat::Rgba8Unorm
+            | wgpu::TextureFormat::Rgba8UnormSrgb
+            | wgpu::TextureFormat::Bgra8Unorm
+            | wgpu::TextureFormat::Bgra8UnormSrgb
+    )
+}
+
+/// Copy the contents of a 2D texture to the CPU.
+///
+/// The texture must have been created with [`wgpu::TextureUsages::COPY_SRC`], and have one of the
+/// formats accepted by [`is_readable_format`].
+/// egui paints in gamma space, so the bytes are returned as they are, as premultiplied colors.
+///
+/// Blocks until the GPU has finished all submitted work, so this is not meant to be called
+/// every frame. Not supported on the web, where the GPU can't be waited for.
+pub(crate) fn read_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+    format: wgpu::TextureFormat,
+    size: [u32; 2],
+) -> Result<epaint::ColorImage, wgpu::BufferAsyncError> {
+    crate::profile_function!();
+    debug_assert!(is_readable_format(format));
+
+    let [width, height] = size;
+    let padded_bytes_per_row = padded_bytes_per_row(width);
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("egui_readback_buffer"),
+        size: padded_bytes_per_row as wgpu::BufferAddress * height as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("egui_readback_encoder"),
+    });
+    encoder.copy_texture_to_buffer(
+        texture.as_image_copy(),
+        wgpu::ImageCopyBuffer {
+            buffer: &buffer,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(padded_bytes_per_row),
+                rows_per_image: None,
+            },
+        },
+        wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+    );
+    queue.submit(std::iter::once(encoder.finish()));
+
+    let (sender, receiver) = std::sync::mpsc::channel();
+    buffer
+        .slice(..)
+        .map_async(wgpu::MapMode::Read, move |result| {
+            sender.send(result).ok();
+        });
+    device.poll(wgpu::Maintain::Wait);
+    receiver.recv().unwrap_or(Err(wgpu::BufferAsyncError))?;
+
+    let image = {
+        let data = buffer.slice(..).get_mapped_range();
+        let is_bgra = matches!(
+            format,
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb
+        );
+        image_from_padded_rows(&data, padded_bytes_per_row, size, is_bgra)
+    };
+    buffer.unmap();
+    Ok(image)
+}
+
+/// Rows copied from a texture to a buffer must be aligned to
+/// [`wgpu::COPY_BYTES_PER_ROW_ALIGNMENT`].
+fn padded_bytes_per_row(width: u32) -> u32 {
+    let bytes_per_row = width * 4;
+    let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    (bytes_per_row + align - 1) / align * align
+}
+
+fn image_from_padded_rows(
+    data: &[u8],
+    padded_bytes_per_row: u32,
+    [width, height]: [u32; 2],
+    is_bgra: bool,
+) -> epaint::ColorImage {
+    let pixels = data
+        .chunks_exact(padded_bytes_per_row as usize)
+        .take(height as usize)
+        .flat_map(|row| row[..width as usize * 4].chunks_exact(4))
+        .map(|p| {
+            if is_bgra {
+                epaint::Color32::from_rgba_premultiplied(p[2], p[1], p[0], p[3])
+            } else {
+                epaint::Color32::from_rgba_premultiplied(p[0], p[1], p[2], p[3])
+            }
+        })
+        .collect();
+    epaint::ColorImage {
+        size: [width as usize, height as usize],
+        pixels,
+    }
+}
+
+#[test]
+fn padded_rows_are_unpadded_and_swizzled() {
+    assert_eq!(padded_bytes_per_row(1), 256);
+    assert_eq!(padded_bytes_per_row(64), 256);
+    assert_eq!(padded_bytes_per_row(65), 512);
+
+    let mut data = vec![0_u8; 2 * 256];
+    data[..8].copy_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]);
+    data[256..260].copy_from_slice(&[9, 10, 11, 12]);
+
+    let image = image_from_padded_rows(&data, 256, [2, 2], false);
+    assert_eq!(image.size, [2, 2]);
+    assert_eq!(
+        image.pixels[0],
+        epaint::Color32::from_rgba_premultiplied(1, 2, 3, 4)
+    );
+    assert_eq!(
+        image.pixels[1],
+        epaint::Color32::from_rgba_premultiplied(5, 6, 7, 8)
+    );
+    assert_eq!(
+        image.pixels[2],
+        epaint::Color32::from_rgba_premultiplied(9, 10, 11, 12)
+    );
+
+    let image = image_from_padded_rows(&data, 256, [2, 2], true);
+    assert_eq!(
+        image.pixels[0],
+        epaint::Color32::from_rgba_premultiplied(3, 2, 1, 4)
+    );
+}
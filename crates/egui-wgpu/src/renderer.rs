@@ -2,7 +2,7 @@
 
 use std::num::NonZeroU64;
 use std::ops::Range;
-use std::{borrow::Cow, collections::HashMap, num::NonZeroU32};
+use std::{borrow::Cow, collections::HashMap, num::NonZeroU32, sync::Arc};
 
 use type_map::concurrent::TypeMap;
 use wgpu;
@@ -66,6 +66,8 @@ impl CallbackFn {
     /// wgpu commands for simple use cases.
     /// This allows reusing the same [`wgpu::CommandEncoder`] for all callbacks and egui
     /// rendering itself.
+    /// This is also where compute passes go, e.g. for a particle simulation: they are recorded
+    /// before egui's render pass, so their results can be used in `paint`.
     ///
     /// For more complicated use cases, one can also return a list of arbitrary
     /// `CommandBuffer`s and have complete control over how they get created and fed.
@@ -92,6 +94,15 @@ impl CallbackFn {
     }
 
     /// Set the paint callback
+    ///
+    /// Any draw command can be issued into the render pass, including indirect ones such as
+    /// [`wgpu::RenderPass::draw_indirect`], e.g. for GPU-driven rendering with the arguments
+    /// written by a compute pass in `prepare`.
+    /// A non-zero `first_instance` in the indirect arguments requires
+    /// [`wgpu::Features::INDIRECT_FIRST_INSTANCE`], and
+    /// [`wgpu::RenderPass::multi_draw_indirect`] requires [`wgpu::Features::MULTI_DRAW_INDIRECT`].
+    /// These have to be requested in the `device_descriptor` of the
+    /// [`WgpuConfiguration`](crate::WgpuConfiguration).
     pub fn paint<F>(mut self, paint: F) -> Self
     where
         F: for<'a, 'b> Fn(PaintCallbackInfo, &'a mut wgpu::RenderPass<'b>, &'b TypeMap)
@@ -105,6 +116,7 @@ impl CallbackFn {
 }
 
 /// Information about the screen used for rendering.
+#[derive(Clone, Copy)]
 pub struct ScreenDescriptor {
     /// Size of the window in physical pixels.
     pub size_in_pixels: [u32; 2],
@@ -113,7 +125,65 @@ pub struct ScreenDescriptor {
     pub pixels_per_point: f32,
 }
 
+impl Default for ScreenDescriptor {
+    fn default() -> Self {
+        Self {
+            size_in_pixels: [0, 0],
+            pixels_per_point: 1.0,
+        }
+    }
+}
+
+// `pixels_per_point` is compared and hashed bitwise, so that `Eq` and `Hash` agree.
+impl PartialEq for ScreenDescriptor {
+    fn eq(&self, other: &Self) -> bool {
+        self.size_in_pixels == other.size_in_pixels
+            && self.pixels_per_point.to_bits() == other.pixels_per_point.to_bits()
+    }
+}
+
+impl Eq for ScreenDescriptor {}
+
+impl std::hash::Hash for ScreenDescriptor {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.size_in_pixels.hash(state);
+        self.pixels_per_point.to_bits().hash(state);
+    }
+}
+
 impl ScreenDescriptor {
+    /// Set the viewport of `render_pass` to cover the whole screen,
+    /// with the full `0.0..=1.0` depth range.
+    pub fn set_viewport(&self, render_pass: &mut wgpu::RenderPass<'_>) {
+        self.set_viewport_at(render_pass, [0, 0]);
+    }
+
+    /// Like [`Self::set_viewport`], but with the top-left corner of the screen at `origin`.
+    fn set_viewport_at(&self, render_pass: &mut wgpu::RenderPass<'_>, origin: [u32; 2]) {
+        render_pass.set_viewport(
+            origin[0] as f32,
+            origin[1] as f32,
+            self.size_in_pixels[0] as f32,
+            self.size_in_pixels[1] as f32,
+            0.0,
+            1.0,
+        );
+    }
+
+    /// Convert a rect in points to `[x, y, width, height]` in physical pixels, clamped to the
+    /// screen, e.g. for [`wgpu::RenderPass::set_scissor_rect`].
+    ///
+    /// This is the same rounding egui uses for the scissor rects of its clip rects.
+    pub fn scale_rect(&self, rect: epaint::Rect) -> [u32; 4] {
+        let ScissorRect {
+            x,
+            y,
+            width,
+            height,
+        } = ScissorRect::new(&rect, self.pixels_per_point, self.size_in_pixels);
+        [x, y, width, height]
+    }
+
     /// size in "logical" points
     fn screen_size_in_points(&self) -> [f32; 2] {
         [
@@ -123,6 +193,181 @@ impl ScreenDescriptor {
     }
 }
 
+/// The result of [`Renderer::update_buffers`].
+pub struct UpdateBuffersResult {
+    /// User-defined command buffers gathered from prepare callbacks.
+    ///
+    /// These must be submitted before the command buffer containing the egui render pass.
+    pub command_buffers: Vec<wgpu::CommandBuffer>,
+
+    /// Bytes of vertex data written to the GPU.
+    ///
+    /// Zero if the geometry was unchanged since the previous call.
+    pub vertex_bytes: u64,
+
+    /// Bytes of index data written to the GPU.
+    ///
+    /// Zero if the geometry was unchanged since the previous call.
+    pub index_bytes: u64,
+
+    /// Bytes of texel data written to the GPU by [`Renderer::update_texture`]
+    /// since the previous call to [`Renderer::update_buffers`].
+    pub texture_bytes: u64,
+
+    /// Allocated size of the vertex buffer in bytes, which grows to fit the largest frame.
+    pub vertex_buffer_bytes: u64,
+
+    /// Allocated size of the index buffer in bytes, which grows to fit the largest frame.
+    pub index_buffer_bytes: u64,
+
+    /// Size of the uniform buffer in bytes, or 0 if push constants are used instead
+    /// (see [`Renderer::push_constants_supported`]).
+    pub uniform_buffer_bytes: u64,
+}
+
+/// A mesh whose vertices and indices are uploaded by [`Renderer::update_buffers_from_raw`],
+/// and drawn by [`Renderer::render_raw`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RawMesh {
+    /// The clip rectangle of the mesh, in points.
+    pub clip_rect: epaint::Rect,
+
+    /// The texture the mesh is drawn with.
+    pub texture_id: epaint::TextureId,
+
+    /// The number of [`Vertex`]es of the mesh.
+    pub vertex_count: u32,
+
+    /// The number of `u32` indices of the mesh.
+    pub index_count: u32,
+}
+
+/// An error from [`Renderer::try_new`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RendererError {
+    /// The shader module or the render pipeline failed validation, e.g. because
+    /// [`RendererDescriptor::texture_bind_group_layout`] is not compatible with the shader.
+    ///
+    /// Contains the error reported by wgpu.
+    InvalidPipeline(String),
+}
+
+impl std::fmt::Display for RendererError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidPipeline(err) => write!(f, "invalid egui render pipeline: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for RendererError {}
+
+/// An error from [`Renderer::update_buffers_from_raw`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GeometryError {
+    /// The length of the vertex data doesn't match the vertex counts of the meshes.
+    VertexDataLength {
+        /// The length in bytes implied by the vertex counts.
+        expected: u64,
+
+        /// The length in bytes of the vertex data.
+        actual: u64,
+    },
+
+    /// The length of the index data doesn't match the index counts of the meshes.
+    IndexDataLength {
+        /// The length in bytes implied by the index counts.
+        expected: u64,
+
+        /// The length in bytes of the index data.
+        actual: u64,
+    },
+}
+
+impl std::fmt::Display for GeometryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::VertexDataLength { expected, actual } => write!(
+                f,
+                "vertex data of {} bytes doesn't match the {} bytes of the meshes",
+                actual, expected
+            ),
+            Self::IndexDataLength { expected, actual } => write!(
+                f,
+                "index data of {} bytes doesn't match the {} bytes of the meshes",
+                actual, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for GeometryError {}
+
+/// An error from [`Renderer::update_texture`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextureError {
+    /// The image is larger than the maximum texture size supported by the device
+    /// ([`wgpu::Limits::max_texture_dimension_2d`]).
+    TooLarge {
+        /// The maximum supported width and height.
+        max: u32,
+
+        /// The requested width and height.
+        requested: (u32, u32),
+    },
+
+    /// A partial update of a texture (an [`epaint::ImageDelta`] with a `pos`) extends outside
+    /// of the texture.
+    SubUpdateOutOfBounds {
+        /// The position of the update in the texture.
+        pos: (u32, u32),
+
+        /// The width and height of the update.
+        size: (u32, u32),
+
+        /// The width and height of the texture.
+        texture_size: (u32, u32),
+    },
+
+    /// A partial update of a texture that was not allocated by a previous full update,
+    /// or that was registered with [`Renderer::register_native_texture`].
+    NotAllocated {
+        /// The id of the texture.
+        id: epaint::TextureId,
+    },
+}
+
+impl std::fmt::Display for TextureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TooLarge { max, requested } => write!(
+                f,
+                "texture of size {}x{} is larger than the maximum texture size of {}",
+                requested.0, requested.1, max
+            ),
+            Self::SubUpdateOutOfBounds {
+                pos,
+                size,
+                texture_size,
+            } => write!(
+                f,
+                "update of size {}x{} at {},{} is outside of the texture of size {}x{}",
+                size.0, size.1, pos.0, pos.1, texture_size.0, texture_size.1
+            ),
+            Self::NotAllocated { id } => {
+                write!(f, "partial update of unallocated texture {:?}", id)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TextureError {}
+
+/// The declaration of the locals in `egui.wgsl`, which is replaced by a push constant
+/// declaration when the device supports them, or to use another
+/// [`RendererDescriptor::screen_uniform_binding`].
+const UNIFORM_LOCALS_DECLARATION: &str = "@group(0) @binding(0) var<uniform> r_locals: Locals;";
+
 /// Uniform buffer used when rendering.
 #[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 #[repr(C)]
@@ -133,30 +378,136 @@ struct UniformBuffer {
     _padding: [u32; 2],
 }
 
+/// A primitive drawn by [`Renderer::render_primitives`].
+enum DrawPrimitive<'a> {
+    Mesh {
+        texture_id: epaint::TextureId,
+        index_count: u32,
+    },
+    Callback(&'a epaint::PaintCallback),
+}
+
 struct SlicedBuffer {
     buffer: wgpu::Buffer,
     slices: Vec<Range<wgpu::BufferAddress>>,
     capacity: wgpu::BufferAddress,
 }
 
+/// Options for creating a [`Renderer`].
+#[derive(Clone, Debug)]
+pub struct RendererDescriptor {
+    /// Format of the color target egui is rendered to.
+    ///
+    /// Should preferably be [`wgpu::TextureFormat::Rgba8Unorm`] or
+    /// [`wgpu::TextureFormat::Bgra8Unorm`], i.e. in gamma-space.
+    pub output_color_format: wgpu::TextureFormat,
+
+    /// Format of the depth target of the render pass egui is rendered in, if any.
+    ///
+    /// egui itself does not use depth, but the pipeline needs to be compatible with the render pass.
+    pub output_depth_format: Option<wgpu::TextureFormat>,
+
+    /// Number of MSAA samples of the color target.
+    pub msaa_samples: u32,
+
+    /// Use this layout for the bind groups of textures instead of creating one, e.g. to share
+    /// the bind groups of textures between egui and the 3D renderer of the application.
+    ///
+    /// The layout must have a filterable float 2D texture at binding 0 and a filtering sampler at
+    /// binding 1, both visible in the fragment stage. Use [`Renderer::try_new`] to get an error
+    /// for an incompatible layout, instead of the validation error wgpu raises otherwise.
+    pub texture_bind_group_layout: Option<Arc<wgpu::BindGroupLayout>>,
+
+    /// The binding of the screen size uniform in bind group 0 (default `0`).
+    ///
+    /// Change it to avoid collisions when sharing the pipeline layout of egui with shaders
+    /// that have their own bindings in group 0.
+    pub screen_uniform_binding: u32,
+
+    /// Prefix of the labels of all wgpu objects created by the renderer, e.g. `"egui[main_window]/"`
+    /// (default empty).
+    ///
+    /// This tells apart the objects of several renderers sharing a device in GPU frame captures.
+    /// See also [`Renderer::set_label_prefix`].
+    pub label_prefix: String,
+}
+
+impl Default for RendererDescriptor {
+    fn default() -> Self {
+        Self {
+            output_color_format: wgpu::TextureFormat::Rgba8Unorm,
+            output_depth_format: None,
+            msaa_samples: 1,
+            texture_bind_group_layout: None,
+            screen_uniform_binding: 0,
+            label_prefix: String::new(),
+        }
+    }
+}
+
 /// Renderer for a egui based GUI.
+///
+/// A renderer may be dropped while commands using its buffers and textures are still in flight,
+/// for the same reason as in [`Self::free_texture`]. `winit::Painter::destroy` still polls the
+/// device to completion, so that the memory is actually released when it returns.
 pub struct Renderer {
     pipeline: wgpu::RenderPipeline,
+    /// Contains both the vertex and the fragment stages of [`Self::pipeline`].
+    shader_module: wgpu::ShaderModule,
+    pipeline_layout: wgpu::PipelineLayout,
+    /// The descriptor [`Self::pipeline`] was created with.
+    descriptor: RendererDescriptor,
+    /// See [`Self::set_msaa_samples`].
+    pending_msaa_change: Option<u32>,
+    /// The polygon mode [`Self::pipeline`] was created with.
+    polygon_mode: wgpu::PolygonMode,
+    /// See [`Self::set_polygon_mode`].
+    pending_polygon_mode_change: Option<wgpu::PolygonMode>,
 
     index_buffer: SlicedBuffer,
+    /// The format of each slice of [`Self::index_buffer`].
+    index_formats: Vec<wgpu::IndexFormat>,
+    /// Reused for converting indices to `u16`.
+    index_scratch: Vec<u16>,
     vertex_buffer: SlicedBuffer,
+    /// Hash of the vertex and index data currently in the buffers.
+    ///
+    /// Used to skip uploading identical geometry two frames in a row.
+    geometry_hash: Option<u64>,
 
     uniform_buffer: wgpu::Buffer,
     uniform_bind_group: wgpu::BindGroup,
-    texture_bind_group_layout: wgpu::BindGroupLayout,
+    /// See [`Self::push_constants_supported`].
+    push_constants_supported: bool,
+    texture_bind_group_layout: Arc<wgpu::BindGroupLayout>,
 
     /// Map of egui texture IDs to textures and their associated bindgroups (texture view +
-    /// sampler). The texture may be None if the TextureId is just a handle to a user-provided
-    /// sampler.
-    textures: HashMap<epaint::TextureId, (Option<wgpu::Texture>, wgpu::BindGroup)>,
+    /// sampler), for the textures managed by egui ([`epaint::TextureId::Managed`]),
+    /// such as the font atlas.
+    managed_textures: HashMap<epaint::TextureId, (Option<wgpu::Texture>, wgpu::BindGroup)>,
+    /// Like [`Self::managed_textures`], but for the textures registered by the application
+    /// ([`epaint::TextureId::User`]). The texture is always None, since they are just handles
+    /// to a user-provided texture view and sampler.
+    user_textures: HashMap<epaint::TextureId, (Option<wgpu::Texture>, wgpu::BindGroup)>,
+    /// Size and format of the textures allocated by [`Self::update_texture`].
+    texture_descs: HashMap<epaint::TextureId, ([u32; 2], wgpu::TextureFormat)>,
     next_user_texture_id: u64,
+    /// See [`Self::bind_group_count`].
+    live_bind_groups: usize,
     samplers: HashMap<epaint::textures::TextureOptions, wgpu::Sampler>,
 
+    /// Bytes uploaded by [`Self::update_texture`] since the last [`Self::update_buffers`].
+    texture_bytes_since_update_buffers: u64,
+
+    /// See [`Self::set_scissor_clamp`].
+    scissor_clamp: bool,
+
+    /// See [`Self::set_debug_groups`].
+    debug_groups: bool,
+
+    /// See [`Self::set_target_origin`].
+    target_origin: [u32; 2],
+
     /// Storage for use by [`epaint::PaintCallback`]'s that need to store resources such as render
     /// pipelines that must have the lifetime of the renderpass.
     pub paint_callback_resources: TypeMap,
@@ -164,25 +515,42 @@ pub struct Renderer {
 
 impl Renderer {
     /// Creates a renderer for a egui UI.
-    ///
-    /// `output_color_format` should preferably be [`wgpu::TextureFormat::Rgba8Unorm`] or
-    /// [`wgpu::TextureFormat::Bgra8Unorm`], i.e. in gamma-space.
-    pub fn new(
-        device: &wgpu::Device,
-        output_color_format: wgpu::TextureFormat,
-        output_depth_format: Option<wgpu::TextureFormat>,
-        msaa_samples: u32,
-    ) -> Self {
+    pub fn new(device: &wgpu::Device, descriptor: &RendererDescriptor) -> Self {
         crate::profile_function!();
 
+        let push_constants_supported = device.features().contains(wgpu::Features::PUSH_CONSTANTS)
+            && device.limits().max_push_constant_size as usize
+                >= std::mem::size_of::<UniformBuffer>();
+
+        let prefix = &descriptor.label_prefix;
+
+        let source = include_str!("egui.wgsl");
+        debug_assert!(source.contains(UNIFORM_LOCALS_DECLARATION));
+        let source = if push_constants_supported {
+            // Same shader, but with the locals in push constants instead of the uniform buffer.
+            Cow::Owned(source.replace(
+                UNIFORM_LOCALS_DECLARATION,
+                "var<push_constant> r_locals: Locals;",
+            ))
+        } else if descriptor.screen_uniform_binding != 0 {
+            Cow::Owned(source.replace(
+                UNIFORM_LOCALS_DECLARATION,
+                &format!(
+                    "@group(0) @binding({}) var<uniform> r_locals: Locals;",
+                    descriptor.screen_uniform_binding
+                ),
+            ))
+        } else {
+            Cow::Borrowed(source)
+        };
         let shader = wgpu::ShaderModuleDescriptor {
-            label: Some("egui"),
-            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("egui.wgsl"))),
+            label: Some(&label(prefix, "egui")),
+            source: wgpu::ShaderSource::Wgsl(source),
         };
         let module = device.create_shader_module(shader);
 
         let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("egui_uniform_buffer"),
+            label: Some(&label(prefix, "egui_uniform_buffer")),
             contents: bytemuck::cast_slice(&[UniformBuffer {
                 screen_size_in_points: [0.0, 0.0],
                 _padding: Default::default(),
@@ -192,9 +560,9 @@ impl Renderer {
 
         let uniform_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: Some("egui_uniform_bind_group_layout"),
+                label: Some(&label(prefix, "egui_uniform_bind_group_layout")),
                 entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
+                    binding: descriptor.screen_uniform_binding,
                     visibility: wgpu::ShaderStages::VERTEX,
                     ty: wgpu::BindingType::Buffer {
                         has_dynamic_offset: false,
@@ -206,10 +574,10 @@ impl Renderer {
             });
 
         let uniform_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("egui_uniform_bind_group"),
+            label: Some(&label(prefix, "egui_uniform_bind_group")),
             layout: &uniform_bind_group_layout,
             entries: &[wgpu::BindGroupEntry {
-                binding: 0,
+                binding: descriptor.screen_uniform_binding,
                 resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
                     buffer: &uniform_buffer,
                     offset: 0,
@@ -218,102 +586,53 @@ impl Renderer {
             }],
         });
 
-        let texture_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: Some("egui_texture_bind_group_layout"),
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            multisampled: false,
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                ],
+        let texture_bind_group_layout = descriptor
+            .texture_bind_group_layout
+            .clone()
+            .unwrap_or_else(|| {
+                Arc::new(
+                    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                        label: Some(&label(prefix, "egui_texture_bind_group_layout")),
+                        entries: &[
+                            wgpu::BindGroupLayoutEntry {
+                                binding: 0,
+                                visibility: wgpu::ShaderStages::FRAGMENT,
+                                ty: wgpu::BindingType::Texture {
+                                    multisampled: false,
+                                    sample_type: wgpu::TextureSampleType::Float {
+                                        filterable: true,
+                                    },
+                                    view_dimension: wgpu::TextureViewDimension::D2,
+                                },
+                                count: None,
+                            },
+                            wgpu::BindGroupLayoutEntry {
+                                binding: 1,
+                                visibility: wgpu::ShaderStages::FRAGMENT,
+                                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                                count: None,
+                            },
+                        ],
+                    }),
+                )
             });
 
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: Some("egui_pipeline_layout"),
+            label: Some(&label(prefix, "egui_pipeline_layout")),
             bind_group_layouts: &[&uniform_bind_group_layout, &texture_bind_group_layout],
-            push_constant_ranges: &[],
-        });
-
-        let depth_stencil = output_depth_format.map(|format| wgpu::DepthStencilState {
-            format,
-            depth_write_enabled: false,
-            depth_compare: wgpu::CompareFunction::Always,
-            stencil: wgpu::StencilState::default(),
-            bias: wgpu::DepthBiasState::default(),
-        });
-
-        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("egui_pipeline"),
-            layout: Some(&pipeline_layout),
-            vertex: wgpu::VertexState {
-                entry_point: "vs_main",
-                module: &module,
-                buffers: &[wgpu::VertexBufferLayout {
-                    array_stride: 5 * 4,
-                    step_mode: wgpu::VertexStepMode::Vertex,
-                    // 0: vec2 position
-                    // 1: vec2 texture coordinates
-                    // 2: uint color
-                    attributes: &wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x2, 2 => Uint32],
-                }],
-            },
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                unclipped_depth: false,
-                conservative: false,
-                cull_mode: None,
-                front_face: wgpu::FrontFace::default(),
-                polygon_mode: wgpu::PolygonMode::default(),
-                strip_index_format: None,
+            push_constant_ranges: if push_constants_supported {
+                &[wgpu::PushConstantRange {
+                    stages: wgpu::ShaderStages::VERTEX,
+                    range: 0..std::mem::size_of::<UniformBuffer>() as u32,
+                }]
+            } else {
+                &[]
             },
-            depth_stencil,
-            multisample: wgpu::MultisampleState {
-                alpha_to_coverage_enabled: false,
-                count: msaa_samples,
-                mask: !0,
-            },
-
-            fragment: Some(wgpu::FragmentState {
-                module: &module,
-                entry_point: if output_color_format.describe().srgb {
-                    tracing::warn!("Detected a linear (sRGBA aware) framebuffer {:?}. egui prefers Rgba8Unorm or Bgra8Unorm", output_color_format);
-                    "fs_main_linear_framebuffer"
-                } else {
-                    "fs_main_gamma_framebuffer" // this is what we prefer
-                },
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: output_color_format,
-                    blend: Some(wgpu::BlendState {
-                        color: wgpu::BlendComponent {
-                            src_factor: wgpu::BlendFactor::One,
-                            dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
-                            operation: wgpu::BlendOperation::Add,
-                        },
-                        alpha: wgpu::BlendComponent {
-                            src_factor: wgpu::BlendFactor::OneMinusDstAlpha,
-                            dst_factor: wgpu::BlendFactor::One,
-                            operation: wgpu::BlendOperation::Add,
-                        },
-                    }),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
-            multiview: None,
         });
 
+        let polygon_mode = wgpu::PolygonMode::Fill;
+        let pipeline = create_pipeline(device, &module, &pipeline_layout, descriptor, polygon_mode);
+
         const VERTEX_BUFFER_START_CAPACITY: wgpu::BufferAddress =
             (std::mem::size_of::<Vertex>() * 1024) as _;
         const INDEX_BUFFER_START_CAPACITY: wgpu::BufferAddress =
@@ -321,27 +640,97 @@ impl Renderer {
 
         Self {
             pipeline,
+            shader_module: module,
+            pipeline_layout,
+            descriptor: descriptor.clone(),
+            pending_msaa_change: None,
+            polygon_mode,
+            pending_polygon_mode_change: None,
             vertex_buffer: SlicedBuffer {
-                buffer: create_vertex_buffer(device, VERTEX_BUFFER_START_CAPACITY),
+                buffer: create_vertex_buffer(device, prefix, VERTEX_BUFFER_START_CAPACITY),
                 slices: Vec::with_capacity(64),
                 capacity: VERTEX_BUFFER_START_CAPACITY,
             },
             index_buffer: SlicedBuffer {
-                buffer: create_index_buffer(device, INDEX_BUFFER_START_CAPACITY),
+                buffer: create_index_buffer(device, prefix, INDEX_BUFFER_START_CAPACITY),
                 slices: Vec::with_capacity(64),
                 capacity: INDEX_BUFFER_START_CAPACITY,
             },
+            index_formats: Vec::with_capacity(64),
+            index_scratch: Vec::new(),
+            geometry_hash: None,
             uniform_buffer,
             uniform_bind_group,
+            push_constants_supported,
             texture_bind_group_layout,
-            textures: HashMap::new(),
+            managed_textures: HashMap::new(),
+            user_textures: HashMap::new(),
+            texture_descs: HashMap::new(),
             next_user_texture_id: 0,
+            // The uniform bind group.
+            live_bind_groups: 1,
             samplers: HashMap::new(),
+            texture_bytes_since_update_buffers: 0,
+            scissor_clamp: true,
+            debug_groups: cfg!(debug_assertions),
+            target_origin: [0, 0],
             paint_callback_resources: TypeMap::default(),
         }
     }
 
+    /// Like [`Self::new`], but checks that the shader module and the render pipeline are valid.
+    ///
+    /// This is useful with a [`RendererDescriptor::texture_bind_group_layout`] provided by the
+    /// application. The check is done with a wgpu error scope, which resolves asynchronously on
+    /// the web.
+    ///
+    /// # Errors
+    /// If wgpu reports a validation error while creating the renderer.
+    pub async fn try_new(
+        device: &wgpu::Device,
+        descriptor: &RendererDescriptor,
+    ) -> Result<Self, RendererError> {
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let renderer = Self::new(device, descriptor);
+        match device.pop_error_scope().await {
+            Some(err) => Err(RendererError::InvalidPipeline(err.to_string())),
+            None => Ok(renderer),
+        }
+    }
+
+    /// Creates a renderer for a egui UI.
+    ///
+    /// `output_color_format` should preferably be [`wgpu::TextureFormat::Rgba8Unorm`] or
+    /// [`wgpu::TextureFormat::Bgra8Unorm`], i.e. in gamma-space.
+    #[deprecated = "Use Renderer::new with a RendererDescriptor"]
+    pub fn new_with_formats(
+        device: &wgpu::Device,
+        output_color_format: wgpu::TextureFormat,
+        output_depth_format: Option<wgpu::TextureFormat>,
+        msaa_samples: u32,
+    ) -> Self {
+        Self::new(
+            device,
+            &RendererDescriptor {
+                output_color_format,
+                output_depth_format,
+                msaa_samples,
+                texture_bind_group_layout: None,
+                screen_uniform_binding: 0,
+                label_prefix: String::new(),
+            },
+        )
+    }
+
     /// Executes the egui renderer onto an existing wgpu renderpass.
+    ///
+    /// The render pass, and with it the choice of load operation, is owned by the caller:
+    /// use [`wgpu::LoadOp::Clear`] to clear the target before egui is painted,
+    /// or [`wgpu::LoadOp::Load`] to paint egui on top of existing content.
+    ///
+    /// Only the buffers built by [`Self::update_buffers`] are read, so this takes `&self`:
+    /// once they are built, several render passes may be recorded through shared references,
+    /// e.g. from different threads each holding a read lock of [`crate::RenderState::renderer`].
     pub fn render<'rp>(
         &'rp self,
         render_pass: &mut wgpu::RenderPass<'rp>,
@@ -349,73 +738,145 @@ impl Renderer {
         screen_descriptor: &ScreenDescriptor,
     ) {
         crate::profile_function!();
+        let _span = tracing::debug_span!("render", primitives = paint_jobs.len()).entered();
+
+        let primitives = paint_jobs.iter().map(
+            |epaint::ClippedPrimitive {
+                 clip_rect,
+                 primitive,
+             }| {
+                let primitive = match primitive {
+                    Primitive::Mesh(mesh) => DrawPrimitive::Mesh {
+                        texture_id: mesh.texture_id,
+                        index_count: mesh.indices.len() as u32,
+                    },
+                    Primitive::Callback(callback) => DrawPrimitive::Callback(callback),
+                };
+                (*clip_rect, primitive)
+            },
+        );
+        self.render_primitives(render_pass, primitives, screen_descriptor);
+    }
 
+    /// Like [`Self::render`], but for meshes uploaded with [`Self::update_buffers_from_raw`].
+    pub fn render_raw<'rp>(
+        &'rp self,
+        render_pass: &mut wgpu::RenderPass<'rp>,
+        meshes: &[RawMesh],
+        screen_descriptor: &ScreenDescriptor,
+    ) {
+        crate::profile_function!();
+        let _span = tracing::debug_span!("render_raw", primitives = meshes.len()).entered();
+
+        let primitives = meshes.iter().map(|mesh| {
+            (
+                mesh.clip_rect,
+                DrawPrimitive::Mesh {
+                    texture_id: mesh.texture_id,
+                    index_count: mesh.index_count,
+                },
+            )
+        });
+        self.render_primitives(render_pass, primitives, screen_descriptor);
+    }
+
+    fn render_primitives<'rp, 'a>(
+        &'rp self,
+        render_pass: &mut wgpu::RenderPass<'rp>,
+        primitives: impl Iterator<Item = (epaint::Rect, DrawPrimitive<'a>)>,
+        screen_descriptor: &ScreenDescriptor,
+    ) {
         let pixels_per_point = screen_descriptor.pixels_per_point;
         let size_in_pixels = screen_descriptor.size_in_pixels;
+        let [origin_x, origin_y] = self.target_origin;
 
         // Whether or not we need to reset the render pass because a paint callback has just
         // run.
         let mut needs_reset = true;
+        // The texture whose bind group is currently set, to skip redundant `set_bind_group`
+        // calls when adjacent meshes share a texture, as is common with the font atlas.
+        let mut bound_texture = None;
 
-        let mut index_buffer_slices = self.index_buffer.slices.iter();
+        let mut index_buffer_slices = self.index_buffer.slices.iter().zip(&self.index_formats);
         let mut vertex_buffer_slices = self.vertex_buffer.slices.iter();
 
-        for epaint::ClippedPrimitive {
-            clip_rect,
-            primitive,
-        } in paint_jobs
-        {
+        for (primitive_index, (clip_rect, primitive)) in primitives.enumerate() {
             if needs_reset {
-                render_pass.set_viewport(
-                    0.0,
-                    0.0,
-                    size_in_pixels[0] as f32,
-                    size_in_pixels[1] as f32,
-                    0.0,
-                    1.0,
-                );
+                screen_descriptor.set_viewport_at(render_pass, self.target_origin);
                 render_pass.set_pipeline(&self.pipeline);
+                // Bound even with push constants, so that textures stay in bind group 1.
                 render_pass.set_bind_group(0, &self.uniform_bind_group, &[]);
+                if self.push_constants_supported {
+                    render_pass.set_push_constants(
+                        wgpu::ShaderStages::VERTEX,
+                        0,
+                        bytemuck::cast_slice(&[UniformBuffer {
+                            screen_size_in_points: screen_descriptor.screen_size_in_points(),
+                            _padding: Default::default(),
+                        }]),
+                    );
+                }
+                bound_texture = None;
                 needs_reset = false;
             }
 
-            {
-                let rect = ScissorRect::new(clip_rect, pixels_per_point, size_in_pixels);
-
-                if rect.width == 0 || rect.height == 0 {
-                    // Skip rendering zero-sized clip areas.
-                    if let Primitive::Mesh(_) = primitive {
-                        // If this is a mesh, we need to advance the index and vertex buffer iterators:
-                        index_buffer_slices.next().unwrap();
-                        vertex_buffer_slices.next().unwrap();
-                    }
-                    continue;
-                }
+            let scissor_rect = if self.scissor_clamp {
+                ScissorRect::new(&clip_rect, pixels_per_point, size_in_pixels)
+            } else {
+                ScissorRect::new_unclamped(&clip_rect, pixels_per_point)
+            };
 
-                render_pass.set_scissor_rect(rect.x, rect.y, rect.width, rect.height);
+            if scissor_rect.width == 0 || scissor_rect.height == 0 {
+                // Skip rendering zero-sized clip areas.
+                if let DrawPrimitive::Mesh { .. } = primitive {
+                    // If this is a mesh, we need to advance the index and vertex buffer iterators:
+                    index_buffer_slices.next().unwrap();
+                    vertex_buffer_slices.next().unwrap();
+                }
+                continue;
             }
 
+            render_pass.set_scissor_rect(
+                origin_x + scissor_rect.x,
+                origin_y + scissor_rect.y,
+                scissor_rect.width,
+                scissor_rect.height,
+            );
+
             match primitive {
-                Primitive::Mesh(mesh) => {
-                    let index_buffer_slice = index_buffer_slices.next().unwrap();
+                DrawPrimitive::Mesh {
+                    texture_id,
+                    index_count,
+                } => {
+                    let (index_buffer_slice, index_format) = index_buffer_slices.next().unwrap();
                     let vertex_buffer_slice = vertex_buffer_slices.next().unwrap();
 
-                    if let Some((_texture, bind_group)) = self.textures.get(&mesh.texture_id) {
-                        render_pass.set_bind_group(1, bind_group, &[]);
+                    if let Some((_texture, bind_group)) = self.texture(&texture_id) {
+                        if self.debug_groups {
+                            render_pass
+                                .push_debug_group(&debug_group_label(primitive_index, &clip_rect));
+                        }
+                        if bound_texture != Some(texture_id) {
+                            render_pass.set_bind_group(1, bind_group, &[]);
+                            bound_texture = Some(texture_id);
+                        }
                         render_pass.set_index_buffer(
                             self.index_buffer.buffer.slice(index_buffer_slice.clone()),
-                            wgpu::IndexFormat::Uint32,
+                            *index_format,
                         );
                         render_pass.set_vertex_buffer(
                             0,
                             self.vertex_buffer.buffer.slice(vertex_buffer_slice.clone()),
                         );
-                        render_pass.draw_indexed(0..mesh.indices.len() as u32, 0, 0..1);
+                        render_pass.draw_indexed(0..index_count, 0, 0..1);
+                        if self.debug_groups {
+                            render_pass.pop_debug_group();
+                        }
                     } else {
-                        tracing::warn!("Missing texture: {:?}", mesh.texture_id);
+                        tracing::warn!("Missing texture: {:?}", texture_id);
                     }
                 }
-                Primitive::Callback(callback) => {
+                DrawPrimitive::Callback(callback) => {
                     let cbfn = if let Some(c) = callback.callback.downcast_ref::<CallbackFn>() {
                         c
                     } else {
@@ -442,8 +903,8 @@ impl Renderer {
                             let max = (callback.rect.max.to_vec2() * pixels_per_point).round();
 
                             render_pass.set_viewport(
-                                min.x,
-                                min.y,
+                                origin_x as f32 + min.x,
+                                origin_y as f32 + min.y,
                                 max.x - min.x,
                                 max.y - min.y,
                                 0.0,
@@ -451,62 +912,131 @@ impl Renderer {
                             );
                         }
 
+                        if self.debug_groups {
+                            render_pass
+                                .push_debug_group(&debug_group_label(primitive_index, &clip_rect));
+                        }
                         (cbfn.paint)(
                             PaintCallbackInfo {
                                 viewport: callback.rect,
-                                clip_rect: *clip_rect,
+                                clip_rect,
                                 pixels_per_point,
                                 screen_size_px: size_in_pixels,
+                                target_origin_px: self.target_origin,
+                                scissor_rect: Some(scissor_rect.to_points(pixels_per_point)),
                             },
                             render_pass,
                             &self.paint_callback_resources,
                         );
+                        if self.debug_groups {
+                            render_pass.pop_debug_group();
+                        }
                     }
                 }
             }
         }
 
-        render_pass.set_scissor_rect(0, 0, size_in_pixels[0], size_in_pixels[1]);
+        render_pass.set_scissor_rect(origin_x, origin_y, size_in_pixels[0], size_in_pixels[1]);
     }
 
     /// Should be called before `render()`.
+    ///
+    /// # Errors
+    /// If the image is larger than the maximum texture size of the device, or if it is a partial
+    /// update of a texture that was never allocated or that doesn't fit in the existing texture.
+    /// Nothing is uploaded in that case.
     pub fn update_texture(
         &mut self,
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         id: epaint::TextureId,
         image_delta: &epaint::ImageDelta,
-    ) {
+    ) -> Result<(), TextureError> {
         crate::profile_function!();
 
         let width = image_delta.image.width() as u32;
         let height = image_delta.image.height() as u32;
-
-        let size = wgpu::Extent3d {
+        let _span = tracing::debug_span!(
+            "update_texture",
+            ?id,
             width,
             height,
-            depth_or_array_layers: 1,
-        };
+            partial = image_delta.pos.is_some(),
+        )
+        .entered();
 
-        let data_color32 = match &image_delta.image {
-            epaint::ImageData::Color(image) => {
-                assert_eq!(
-                    width as usize * height as usize,
-                    image.pixels.len(),
-                    "Mismatch between texture size and texel count"
-                );
-                Cow::Borrowed(&image.pixels)
+        let max = device.limits().max_texture_dimension_2d;
+        if width > max || height > max {
+            return Err(TextureError::TooLarge {
+                max,
+                requested: (width, height),
+            });
+        }
+        if let Some(pos) = image_delta.pos {
+            // Textures registered with `register_native_texture` have no texture to write to.
+            let [texture_width, texture_height] =
+                match (self.texture(&id), self.texture_descs.get(&id)) {
+                    (Some((Some(_), _)), Some((size, _))) => *size,
+                    _ => return Err(TextureError::NotAllocated { id }),
+                };
+            let (x, y) = (pos[0] as u32, pos[1] as u32);
+            if x.saturating_add(width) > texture_width || y.saturating_add(height) > texture_height
+            {
+                return Err(TextureError::SubUpdateOutOfBounds {
+                    pos: (x, y),
+                    size: (width, height),
+                    texture_size: (texture_width, texture_height),
+                });
             }
+        }
+
+        let data_color32 = match &image_delta.image {
+            epaint::ImageData::Color(image) => Cow::Borrowed(&image.pixels),
             epaint::ImageData::Font(image) => {
-                assert_eq!(
-                    width as usize * height as usize,
-                    image.pixels.len(),
-                    "Mismatch between texture size and texel count"
-                );
                 Cow::Owned(image.srgba_pixels(None).collect::<Vec<_>>())
             }
         };
-        let data_bytes: &[u8] = bytemuck::cast_slice(data_color32.as_slice());
+        assert_eq!(
+            width as usize * height as usize,
+            data_color32.len(),
+            "Mismatch between texture size and texel count"
+        );
+
+        // Textures are always Rgba, also when the target is Bgra: the channel order of a texture
+        // only matters for how the GPU stores it, and sampling returns rgba either way.
+        self.upload_image(
+            device,
+            queue,
+            id,
+            image_delta,
+            bytemuck::cast_slice(data_color32.as_slice()),
+            wgpu::TextureFormat::Rgba8UnormSrgb, // Minspec for wgpu WebGL emulation is WebGL2, so this should always be supported.
+        );
+
+        Ok(())
+    }
+
+    /// Upload the texels of `image_delta`, already converted to `format`, to the texture `id`.
+    ///
+    /// Allocates a new texture unless `image_delta` is a partial update.
+    fn upload_image(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        id: epaint::TextureId,
+        image_delta: &epaint::ImageDelta,
+        data: &[u8],
+        format: wgpu::TextureFormat,
+    ) {
+        let width = image_delta.image.width() as u32;
+        let height = image_delta.image.height() as u32;
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let bytes_per_texel = format.describe().block_size as u32;
+        self.texture_bytes_since_update_buffers += data.len() as u64;
 
         let queue_write_data_to_texture = |texture, origin| {
             queue.write_texture(
@@ -516,10 +1046,10 @@ impl Renderer {
                     origin,
                     aspect: wgpu::TextureAspect::All,
                 },
-                data_bytes,
+                data,
                 wgpu::ImageDataLayout {
                     offset: 0,
-                    bytes_per_row: NonZeroU32::new(4 * width),
+                    bytes_per_row: NonZeroU32::new(bytes_per_texel * width),
                     rows_per_image: NonZeroU32::new(height),
                 },
                 size,
@@ -527,10 +1057,9 @@ impl Renderer {
         };
 
         if let Some(pos) = image_delta.pos {
-            // update the existing texture
+            // update the existing texture, which `update_texture` checked is allocated
             let (texture, _bind_group) = self
-                .textures
-                .get(&id)
+                .texture(&id)
                 .expect("Tried to update a texture that has not been allocated yet.");
             let origin = wgpu::Origin3d {
                 x: pos[0] as u32,
@@ -544,7 +1073,7 @@ impl Renderer {
         } else {
             // allocate a new texture
             // Use same label for all resources associated with this texture id (no point in retyping the type)
-            let label_str = format!("egui_texid_{:?}", id);
+            let label_str = format!("{}egui_texid_{:?}", self.descriptor.label_prefix, id);
             let label = Some(label_str.as_str());
             let texture = device.create_texture(&wgpu::TextureDescriptor {
                 label,
@@ -552,13 +1081,15 @@ impl Renderer {
                 mip_level_count: 1,
                 sample_count: 1,
                 dimension: wgpu::TextureDimension::D2,
-                format: wgpu::TextureFormat::Rgba8UnormSrgb, // Minspec for wgpu WebGL emulation is WebGL2, so this should always be supported.
-                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                format,
+                // COPY_SRC is for `Self::read_texture`.
+                usage: wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::COPY_DST
+                    | wgpu::TextureUsages::COPY_SRC,
+            });
+            let sampler = self.samplers.entry(image_delta.options).or_insert_with(|| {
+                create_sampler(image_delta.options, device, &self.descriptor.label_prefix)
             });
-            let sampler = self
-                .samplers
-                .entry(image_delta.options)
-                .or_insert_with(|| create_sampler(image_delta.options, device));
             let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
                 label,
                 layout: &self.texture_bind_group_layout,
@@ -577,12 +1108,70 @@ impl Renderer {
             });
             let origin = wgpu::Origin3d::ZERO;
             queue_write_data_to_texture(&texture, origin);
-            self.textures.insert(id, (Some(texture), bind_group));
+            if self
+                .texture_map_mut(&id)
+                .insert(id, (Some(texture), bind_group))
+                .is_none()
+            {
+                self.live_bind_groups += 1;
+            }
+            self.texture_descs.insert(id, ([width, height], format));
         };
     }
 
+    /// Free the texture and bind group of the given texture id.
+    ///
+    /// It is safe to call this while recorded or submitted command buffers still use the
+    /// texture: wgpu keeps the underlying GPU resources alive until the GPU is done with them,
+    /// so there is no need to defer this until the submission has completed.
     pub fn free_texture(&mut self, id: &epaint::TextureId) {
-        self.textures.remove(id);
+        let _span = tracing::debug_span!("free_texture", ?id).entered();
+        if self.texture_map_mut(id).remove(id).is_some() {
+            self.live_bind_groups -= 1;
+        }
+        self.texture_descs.remove(id);
+    }
+
+    /// Free all textures registered by the application, e.g. with
+    /// [`Self::register_native_texture`], while keeping the textures managed by egui
+    /// (such as the font atlas).
+    ///
+    /// Useful when switching to a different UI that does not use the same user textures.
+    pub fn free_user_textures(&mut self) {
+        self.live_bind_groups -= self.user_textures.len();
+        self.user_textures.clear();
+        self.texture_descs
+            .retain(|id, _| matches!(id, epaint::TextureId::Managed(_)));
+    }
+
+    /// Free all textures and paint callback resources.
+    ///
+    /// Useful when shutting down while the renderer is still shared with other owners.
+    pub fn free_all_resources(&mut self) {
+        self.live_bind_groups -= self.texture_count();
+        self.managed_textures.clear();
+        self.user_textures.clear();
+        self.texture_descs.clear();
+        self.paint_callback_resources = TypeMap::default();
+    }
+
+    /// The number of textures currently held by the renderer, both those managed by egui
+    /// (such as the font atlas) and those registered by the application.
+    ///
+    /// Useful for detecting texture leaks, since the count should go back to a baseline
+    /// once the UI that uses the textures is gone.
+    pub fn texture_count(&self) -> usize {
+        self.managed_textures.len() + self.user_textures.len()
+    }
+
+    /// The number of [`wgpu::BindGroup`]s currently held by the renderer: one for the uniform
+    /// buffer, and one per texture.
+    ///
+    /// It is counted up when a bind group is added and down when one is dropped. Replacing a
+    /// texture replaces its bind group, so this should only grow when textures are registered
+    /// without ever being freed.
+    pub fn bind_group_count(&self) -> usize {
+        self.live_bind_groups
     }
 
     /// Get the WGPU texture and bind group associated to a texture that has been allocated by egui.
@@ -594,7 +1183,180 @@ impl Renderer {
         &self,
         id: &epaint::TextureId,
     ) -> Option<&(Option<wgpu::Texture>, wgpu::BindGroup)> {
-        self.textures.get(id)
+        match id {
+            epaint::TextureId::Managed(_) => self.managed_textures.get(id),
+            epaint::TextureId::User(_) => self.user_textures.get(id),
+        }
+    }
+
+    /// Download the texels of a texture allocated by [`Self::update_texture`] from the GPU,
+    /// e.g. to check the font atlas in a test.
+    ///
+    /// Returns `None` for unknown textures and for those registered with
+    /// [`Self::register_native_texture`].
+    /// This blocks until the GPU has finished all submitted work.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn read_texture(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        id: &epaint::TextureId,
+    ) -> Option<Result<epaint::ColorImage, wgpu::BufferAsyncError>> {
+        let (size, format) = *self.texture_descs.get(id)?;
+        let texture = self.texture(id)?.0.as_ref()?;
+        if !crate::readback::is_readable_format(format) {
+            return None;
+        }
+        Some(crate::readback::read_texture(
+            device, queue, texture, format, size,
+        ))
+    }
+
+    fn texture_map_mut(
+        &mut self,
+        id: &epaint::TextureId,
+    ) -> &mut HashMap<epaint::TextureId, (Option<wgpu::Texture>, wgpu::BindGroup)> {
+        match id {
+            epaint::TextureId::Managed(_) => &mut self.managed_textures,
+            epaint::TextureId::User(_) => &mut self.user_textures,
+        }
+    }
+
+    /// The render pipeline egui draws its meshes with.
+    ///
+    /// Useful for [`epaint::PaintCallback`]s that draw over the same geometry as egui,
+    /// e.g. with [`wgpu::RenderPass::set_pipeline`] in their paint stage.
+    pub fn pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.pipeline
+    }
+
+    /// The layout of the bind group of each texture, which [`Self::pipeline`] uses as group 1.
+    ///
+    /// It has the texture at binding 0 and the sampler at binding 1.
+    /// Use it to create pipelines that sample egui textures with the bind groups returned by
+    /// [`Self::texture`], without having to replicate the layout.
+    /// To share a layout created by the application instead, see
+    /// [`RendererDescriptor::texture_bind_group_layout`].
+    pub fn texture_bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.texture_bind_group_layout
+    }
+
+    /// The buffer holding the [`Vertex`]es of the meshes uploaded by the last
+    /// [`Self::update_buffers`], or `None` if no meshes have been uploaded.
+    ///
+    /// The vertices of each mesh are stored one after the other, in the order of the paint
+    /// jobs. Paint callbacks can e.g. bind it as a storage buffer to process egui's geometry
+    /// in a compute shader. It may be recreated when it has to grow.
+    pub fn vertex_buffer(&self) -> Option<&wgpu::Buffer> {
+        (!self.vertex_buffer.slices.is_empty()).then_some(&self.vertex_buffer.buffer)
+    }
+
+    /// The buffer holding the indices of the meshes uploaded by the last
+    /// [`Self::update_buffers`], or `None` if no meshes have been uploaded.
+    ///
+    /// Like with [`Self::vertex_buffer`], the meshes are stored in the order of the paint jobs.
+    /// Each mesh's indices are relative to its first vertex, and are `u16` for meshes of at
+    /// most 65535 vertices and `u32` otherwise. [`Self::update_buffers_from_raw`] always
+    /// uploads `u32` indices.
+    pub fn index_buffer(&self) -> Option<&wgpu::Buffer> {
+        (!self.index_buffer.slices.is_empty()).then_some(&self.index_buffer.buffer)
+    }
+
+    /// The shader module of the vertex stage of [`Self::pipeline`], with entry point `vs_main`.
+    pub fn vertex_shader_module(&self) -> &wgpu::ShaderModule {
+        &self.shader_module
+    }
+
+    /// The shader module of the fragment stage of [`Self::pipeline`].
+    ///
+    /// The entry point is `fs_main_gamma_framebuffer`, or `fs_main_linear_framebuffer`
+    /// when rendering to an sRGB target.
+    /// Currently this is the same module as [`Self::vertex_shader_module`].
+    pub fn fragment_shader_module(&self) -> &wgpu::ShaderModule {
+        &self.shader_module
+    }
+
+    /// Whether the screen size is passed to [`Self::pipeline`] in push constants, rather than
+    /// in a uniform buffer that has to be updated every frame.
+    ///
+    /// Push constants are used if the device was created with [`wgpu::Features::PUSH_CONSTANTS`]
+    /// and a [`wgpu::Limits::max_push_constant_size`] of at least 16 bytes. They are never
+    /// available on the web. Either way the textures are in bind group 1.
+    pub fn push_constants_supported(&self) -> bool {
+        self.push_constants_supported
+    }
+
+    /// Size of the uniform buffer read by [`Self::pipeline`], which is not used with push constants.
+    fn uniform_buffer_bytes(&self) -> u64 {
+        if self.push_constants_supported {
+            0
+        } else {
+            std::mem::size_of::<UniformBuffer>() as u64
+        }
+    }
+
+    /// The number of MSAA samples of the color target the pipeline is currently compatible with.
+    pub fn msaa_samples(&self) -> u32 {
+        self.descriptor.msaa_samples
+    }
+
+    /// Change the number of MSAA samples of the color target egui is rendered to.
+    ///
+    /// The pipeline is recreated at the start of the next [`Self::update_buffers`], so the
+    /// render pass passed to the following [`Self::render`] must use the new sample count.
+    pub fn set_msaa_samples(&mut self, msaa_samples: u32) {
+        self.pending_msaa_change =
+            (msaa_samples != self.descriptor.msaa_samples).then_some(msaa_samples);
+    }
+
+    /// Change how the triangles of egui meshes are rasterized, e.g. to
+    /// [`wgpu::PolygonMode::Line`] to draw them as wireframes while debugging tessellation.
+    ///
+    /// Any mode but [`wgpu::PolygonMode::Fill`] requires the device to have been created with
+    /// [`wgpu::Features::POLYGON_MODE_LINE`] or [`wgpu::Features::POLYGON_MODE_POINT`].
+    /// Like with [`Self::set_msaa_samples`], the pipeline is recreated at the start of the next
+    /// [`Self::update_buffers`].
+    pub fn set_polygon_mode(&mut self, polygon_mode: wgpu::PolygonMode) {
+        self.pending_polygon_mode_change =
+            (polygon_mode != self.polygon_mode).then_some(polygon_mode);
+    }
+
+    /// Whether scissor rects are clamped to the bounds of the render target (default `true`).
+    ///
+    /// Rounding the clip rects to physical pixels can produce scissor rects that extend
+    /// outside of the target, which is a wgpu validation error.
+    /// Only disable this for debugging clipping issues.
+    pub fn set_scissor_clamp(&mut self, scissor_clamp: bool) {
+        self.scissor_clamp = scissor_clamp;
+    }
+
+    /// Prefix the labels of the wgpu objects created by the renderer from now on,
+    /// e.g. with `"egui[main_window]/"`.
+    ///
+    /// Objects created before this is called, such as the shader module, keep their labels.
+    /// Set [`RendererDescriptor::label_prefix`] to label all of them.
+    pub fn set_label_prefix(&mut self, prefix: impl Into<String>) {
+        self.descriptor.label_prefix = prefix.into();
+    }
+
+    /// Render to a sub-region of the render target, whose top-left corner is at `origin`
+    /// (in physical pixels) and whose size is the `size_in_pixels` of the [`ScreenDescriptor`]
+    /// passed to [`Self::render`]. Defaults to `[0, 0]`.
+    ///
+    /// The viewport and the scissor rects of [`Self::render`] are offset accordingly,
+    /// including the default viewport of paint callbacks.
+    /// The region must lie within the render target.
+    pub fn set_target_origin(&mut self, origin: [u32; 2]) {
+        self.target_origin = origin;
+    }
+
+    /// Wrap the draw call of each [`epaint::ClippedPrimitive`] in a debug group labeled with its
+    /// index and clip rect, e.g. `egui/primitive[3] [[0.0 0.0] - [100.0 20.0]]`.
+    ///
+    /// This helps identifying which part of the UI a draw call belongs to in RenderDoc or Xcode.
+    /// Since the labels are formatted every frame, this is only enabled by default in debug builds.
+    pub fn set_debug_groups(&mut self, debug_groups: bool) {
+        self.debug_groups = debug_groups;
     }
 
     /// Registers a `wgpu::Texture` with a `epaint::TextureId`.
@@ -613,7 +1375,13 @@ impl Renderer {
             device,
             texture,
             wgpu::SamplerDescriptor {
-                label: Some(format!("egui_user_image_{}", self.next_user_texture_id).as_str()),
+                label: Some(
+                    format!(
+                        "{}egui_user_image_{}",
+                        self.descriptor.label_prefix, self.next_user_texture_id
+                    )
+                    .as_str(),
+                ),
                 mag_filter: texture_filter,
                 min_filter: texture_filter,
                 ..Default::default()
@@ -635,7 +1403,13 @@ impl Renderer {
             device,
             texture,
             wgpu::SamplerDescriptor {
-                label: Some(format!("egui_user_image_{}", self.next_user_texture_id).as_str()),
+                label: Some(
+                    format!(
+                        "{}egui_user_image_{}",
+                        self.descriptor.label_prefix, self.next_user_texture_id
+                    )
+                    .as_str(),
+                ),
                 mag_filter: texture_filter,
                 min_filter: texture_filter,
                 ..Default::default()
@@ -668,7 +1442,13 @@ impl Renderer {
         });
 
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some(format!("egui_user_image_{}", self.next_user_texture_id).as_str()),
+            label: Some(
+                format!(
+                    "{}egui_user_image_{}",
+                    self.descriptor.label_prefix, self.next_user_texture_id
+                )
+                .as_str(),
+            ),
             layout: &self.texture_bind_group_layout,
             entries: &[
                 wgpu::BindGroupEntry {
@@ -683,7 +1463,8 @@ impl Renderer {
         });
 
         let id = epaint::TextureId::User(self.next_user_texture_id);
-        self.textures.insert(id, (None, bind_group));
+        self.user_textures.insert(id, (None, bind_group));
+        self.live_bind_groups += 1;
         self.next_user_texture_id += 1;
 
         id
@@ -703,18 +1484,19 @@ impl Renderer {
     ) {
         crate::profile_function!();
 
-        let (_user_texture, user_texture_binding) = self
-            .textures
-            .get_mut(&id)
-            .expect("Tried to update a texture that has not been allocated yet.");
-
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
             compare: None,
             ..sampler_descriptor
         });
 
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some(format!("egui_user_image_{}", self.next_user_texture_id).as_str()),
+            label: Some(
+                format!(
+                    "{}egui_user_image_{}",
+                    self.descriptor.label_prefix, self.next_user_texture_id
+                )
+                .as_str(),
+            ),
             layout: &self.texture_bind_group_layout,
             entries: &[
                 wgpu::BindGroupEntry {
@@ -728,13 +1510,21 @@ impl Renderer {
             ],
         });
 
+        let (_user_texture, user_texture_binding) = self
+            .texture_map_mut(&id)
+            .get_mut(&id)
+            .expect("Tried to update a texture that has not been allocated yet.");
         *user_texture_binding = bind_group;
     }
 
     /// Uploads the uniform, vertex and index data used by the renderer.
     /// Should be called before `render()`.
     ///
-    /// Returns all user-defined command buffers gathered from prepare callbacks.
+    /// If the vertex and index data of `paint_jobs` is identical to that of the previous call,
+    /// the upload is skipped and the existing buffers are reused.
+    ///
+    /// Returns all user-defined command buffers gathered from prepare callbacks,
+    /// and the number of bytes uploaded.
     pub fn update_buffers(
         &mut self,
         device: &wgpu::Device,
@@ -742,71 +1532,54 @@ impl Renderer {
         encoder: &mut wgpu::CommandEncoder,
         paint_jobs: &[epaint::ClippedPrimitive],
         screen_descriptor: &ScreenDescriptor,
-    ) -> Vec<wgpu::CommandBuffer> {
+    ) -> UpdateBuffersResult {
         crate::profile_function!();
+        let span = tracing::debug_span!(
+            "update_buffers",
+            primitives = paint_jobs.len(),
+            vertex_bytes = tracing::field::Empty,
+            index_bytes = tracing::field::Empty,
+            uploaded = tracing::field::Empty,
+        );
+        let _entered = span.enter();
 
-        let screen_size_in_points = screen_descriptor.screen_size_in_points();
-
-        {
-            crate::profile_scope!("uniforms");
-            // Update uniform buffer
-            queue.write_buffer(
-                &self.uniform_buffer,
-                0,
-                bytemuck::cast_slice(&[UniformBuffer {
-                    screen_size_in_points,
-                    _padding: Default::default(),
-                }]),
-            );
-        }
+        self.update_pipeline_and_uniforms(device, queue, screen_descriptor);
 
-        // Determine how many vertices & indices need to be rendered.
-        let (vertex_count, index_count) = {
-            crate::profile_scope!("count_vertices_indices");
-            paint_jobs.iter().fold((0, 0), |acc, clipped_primitive| {
-                match &clipped_primitive.primitive {
-                    Primitive::Mesh(mesh) => {
-                        (acc.0 + mesh.vertices.len(), acc.1 + mesh.indices.len())
-                    }
-                    Primitive::Callback(_) => acc,
-                }
-            })
+        let geometry_hash = {
+            crate::profile_scope!("hash_geometry");
+            hash_geometry(paint_jobs)
         };
+        let upload_geometry = self.geometry_hash != Some(geometry_hash);
+        self.geometry_hash = Some(geometry_hash);
 
-        {
-            // Resize index buffer if needed:
-            self.index_buffer.slices.clear();
-            let required_size = (std::mem::size_of::<u32>() * index_count) as u64;
-            if self.index_buffer.capacity < required_size {
-                self.index_buffer.capacity =
-                    (self.index_buffer.capacity * 2).at_least(required_size);
-                self.index_buffer.buffer = create_index_buffer(device, self.index_buffer.capacity);
-            }
-        }
-
-        {
-            // Resize vertex buffer if needed:
-            self.vertex_buffer.slices.clear();
-            let required_size = (std::mem::size_of::<Vertex>() * vertex_count) as u64;
-            if self.vertex_buffer.capacity < required_size {
-                self.vertex_buffer.capacity =
-                    (self.vertex_buffer.capacity * 2).at_least(required_size);
-                self.vertex_buffer.buffer =
-                    create_vertex_buffer(device, self.vertex_buffer.capacity);
-            }
-        }
-
-        // Upload index & vertex data and call user callbacks
-        let mut user_cmd_bufs = Vec::new(); // collect user command buffers
+        // Determine how many vertices & indices need to be rendered.
+        let (vertex_count, index_count) = count_vertices_indices(paint_jobs);
+        span.record("vertex_bytes", std::mem::size_of::<Vertex>() * vertex_count);
+        span.record("index_bytes", std::mem::size_of::<u32>() * index_count);
+        span.record("uploaded", upload_geometry);
+
+        // Upload index & vertex data
+        let mut vertex_bytes = 0;
+        let mut index_bytes = 0;
+
+        if upload_geometry {
+            // This is an upper bound for the indices, since small meshes use `u16` indices.
+            self.reserve_geometry(
+                device,
+                (std::mem::size_of::<Vertex>() * vertex_count) as u64,
+                (std::mem::size_of::<u32>() * index_count) as u64,
+            );
 
-        crate::profile_scope!("primitives");
-        for epaint::ClippedPrimitive { primitive, .. } in paint_jobs.iter() {
-            match primitive {
-                Primitive::Mesh(mesh) => {
+            crate::profile_scope!("primitives");
+            for epaint::ClippedPrimitive { primitive, .. } in paint_jobs {
+                if let Primitive::Mesh(mesh) = primitive {
                     {
                         let index_offset = self.index_buffer.slices.last().unwrap_or(&(0..0)).end;
-                        let data = bytemuck::cast_slice(&mesh.indices);
+                        let (data, format) =
+                            index_data(&mesh.indices, mesh.vertices.len(), &mut self.index_scratch);
+                        self.index_formats.push(format);
                         queue.write_buffer(&self.index_buffer.buffer, index_offset, data);
+                        index_bytes += data.len() as u64;
                         self.index_buffer
                             .slices
                             .push(index_offset..(data.len() as wgpu::BufferAddress + index_offset));
@@ -815,37 +1588,308 @@ impl Renderer {
                         let vertex_offset = self.vertex_buffer.slices.last().unwrap_or(&(0..0)).end;
                         let data = bytemuck::cast_slice(&mesh.vertices);
                         queue.write_buffer(&self.vertex_buffer.buffer, vertex_offset, data);
+                        vertex_bytes += data.len() as u64;
                         self.vertex_buffer.slices.push(
                             vertex_offset..(data.len() as wgpu::BufferAddress + vertex_offset),
                         );
                     }
                 }
-                Primitive::Callback(callback) => {
-                    let cbfn = if let Some(c) = callback.callback.downcast_ref::<CallbackFn>() {
-                        c
-                    } else {
-                        tracing::warn!("Unknown paint callback: expected `egui_wgpu::CallbackFn`");
-                        continue;
-                    };
+            }
+        }
 
-                    crate::profile_scope!("callback");
-                    user_cmd_bufs.extend((cbfn.prepare)(
-                        device,
-                        queue,
-                        encoder,
-                        &mut self.paint_callback_resources,
-                    ));
-                }
+        UpdateBuffersResult {
+            command_buffers: self.prepare_callbacks(device, queue, encoder, paint_jobs),
+            vertex_bytes,
+            index_bytes,
+            texture_bytes: std::mem::take(&mut self.texture_bytes_since_update_buffers),
+            vertex_buffer_bytes: self.vertex_buffer.capacity,
+            index_buffer_bytes: self.index_buffer.capacity,
+            uniform_buffer_bytes: self.uniform_buffer_bytes(),
+        }
+    }
+
+    /// Like [`Self::update_buffers`], but uploads vertex and index data prepared by the caller,
+    /// e.g. on a background thread, instead of building it from [`epaint::Mesh`]es.
+    ///
+    /// `vertex_data` must hold the [`Vertex`]es and `index_data` the `u32` indices of all
+    /// `meshes`, concatenated in the same order. Each mesh's indices are relative to its
+    /// first vertex, like in [`epaint::Mesh`]. The data is uploaded as is, without comparing it
+    /// to that of the previous frame. Draw the meshes with [`Self::render_raw`].
+    ///
+    /// Paint callbacks are not supported by this path.
+    ///
+    /// # Errors
+    /// If the length of `vertex_data` or `index_data` doesn't match the counts of `meshes`.
+    /// Nothing is uploaded in that case.
+    pub fn update_buffers_from_raw(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        meshes: &[RawMesh],
+        screen_descriptor: &ScreenDescriptor,
+        vertex_data: &[u8],
+        index_data: &[u8],
+    ) -> Result<UpdateBuffersResult, GeometryError> {
+        crate::profile_function!();
+        let _span = tracing::debug_span!(
+            "update_buffers_from_raw",
+            primitives = meshes.len(),
+            vertex_bytes = vertex_data.len(),
+            index_bytes = index_data.len(),
+        )
+        .entered();
+
+        let vertex_size =
+            |mesh: &RawMesh| std::mem::size_of::<Vertex>() as u64 * mesh.vertex_count as u64;
+        let index_size =
+            |mesh: &RawMesh| std::mem::size_of::<u32>() as u64 * mesh.index_count as u64;
+
+        let expected = meshes.iter().map(vertex_size).sum();
+        if vertex_data.len() as u64 != expected {
+            return Err(GeometryError::VertexDataLength {
+                expected,
+                actual: vertex_data.len() as u64,
+            });
+        }
+        let expected = meshes.iter().map(index_size).sum();
+        if index_data.len() as u64 != expected {
+            return Err(GeometryError::IndexDataLength {
+                expected,
+                actual: index_data.len() as u64,
+            });
+        }
+
+        self.update_pipeline_and_uniforms(device, queue, screen_descriptor);
+
+        // The next call to `update_buffers` must upload its geometry.
+        self.geometry_hash = None;
+        self.reserve_geometry(device, vertex_data.len() as u64, index_data.len() as u64);
+        if !vertex_data.is_empty() {
+            queue.write_buffer(&self.vertex_buffer.buffer, 0, vertex_data);
+        }
+        if !index_data.is_empty() {
+            queue.write_buffer(&self.index_buffer.buffer, 0, index_data);
+        }
+
+        let (mut vertex_offset, mut index_offset) = (0, 0);
+        for mesh in meshes {
+            let (vertex_size, index_size) = (vertex_size(mesh), index_size(mesh));
+            self.vertex_buffer
+                .slices
+                .push(vertex_offset..vertex_offset + vertex_size);
+            self.index_buffer
+                .slices
+                .push(index_offset..index_offset + index_size);
+            self.index_formats.push(wgpu::IndexFormat::Uint32);
+            vertex_offset += vertex_size;
+            index_offset += index_size;
+        }
+
+        Ok(UpdateBuffersResult {
+            command_buffers: Vec::new(),
+            vertex_bytes: vertex_data.len() as u64,
+            index_bytes: index_data.len() as u64,
+            texture_bytes: std::mem::take(&mut self.texture_bytes_since_update_buffers),
+            vertex_buffer_bytes: self.vertex_buffer.capacity,
+            index_buffer_bytes: self.index_buffer.capacity,
+            uniform_buffer_bytes: self.uniform_buffer_bytes(),
+        })
+    }
+
+    /// Recreate [`Self::pipeline`] right away, applying the changes made with
+    /// [`Self::set_msaa_samples`] and [`Self::set_polygon_mode`], instead of at the start of the
+    /// next [`Self::update_buffers`].
+    ///
+    /// The bind group layouts are kept, so the bind groups of the textures stay valid.
+    /// `device` must be the device the renderer was created with.
+    pub fn recreate_pipeline(&mut self, device: &wgpu::Device) {
+        crate::profile_function!();
+        self.apply_pipeline_changes(device, true);
+    }
+
+    /// Recreate the pipeline if `force` is set, or if the MSAA sample count or the polygon mode
+    /// changed.
+    fn apply_pipeline_changes(&mut self, device: &wgpu::Device, force: bool) {
+        if force || self.pending_msaa_change.is_some() || self.pending_polygon_mode_change.is_some()
+        {
+            crate::profile_scope!("recreate_pipeline");
+            if let Some(msaa_samples) = self.pending_msaa_change.take() {
+                self.descriptor.msaa_samples = msaa_samples;
+            }
+            if let Some(polygon_mode) = self.pending_polygon_mode_change.take() {
+                self.polygon_mode = polygon_mode;
             }
+            self.pipeline = create_pipeline(
+                device,
+                &self.shader_module,
+                &self.pipeline_layout,
+                &self.descriptor,
+                self.polygon_mode,
+            );
+        }
+    }
+
+    /// Recreate the pipeline if the MSAA sample count or the polygon mode changed,
+    /// and update the uniform buffer, unless push constants are used instead.
+    fn update_pipeline_and_uniforms(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        screen_descriptor: &ScreenDescriptor,
+    ) {
+        self.apply_pipeline_changes(device, false);
+
+        if self.push_constants_supported {
+            // The screen size is pushed in `render`.
+            return;
+        }
+
+        let screen_size_in_points = screen_descriptor.screen_size_in_points();
+
+        {
+            crate::profile_scope!("uniforms");
+            // Update uniform buffer
+            queue.write_buffer(
+                &self.uniform_buffer,
+                0,
+                bytemuck::cast_slice(&[UniformBuffer {
+                    screen_size_in_points,
+                    _padding: Default::default(),
+                }]),
+            );
+        }
+    }
+
+    /// Clear the slices of the vertex and index buffers, and grow the buffers if needed
+    /// to hold the given number of bytes.
+    fn reserve_geometry(&mut self, device: &wgpu::Device, vertex_bytes: u64, index_bytes: u64) {
+        // Resize index buffer if needed.
+        self.index_buffer.slices.clear();
+        self.index_formats.clear();
+        if self.index_buffer.capacity < index_bytes {
+            let old_capacity = self.index_buffer.capacity;
+            self.index_buffer.capacity = (self.index_buffer.capacity * 2).at_least(index_bytes);
+            tracing::debug!(
+                "egui-wgpu: index buffer grown from {} to {} bytes",
+                old_capacity,
+                self.index_buffer.capacity
+            );
+            self.index_buffer.buffer = create_index_buffer(
+                device,
+                &self.descriptor.label_prefix,
+                self.index_buffer.capacity,
+            );
+        }
+
+        // Resize vertex buffer if needed:
+        self.vertex_buffer.slices.clear();
+        if self.vertex_buffer.capacity < vertex_bytes {
+            let old_capacity = self.vertex_buffer.capacity;
+            self.vertex_buffer.capacity = (self.vertex_buffer.capacity * 2).at_least(vertex_bytes);
+            tracing::debug!(
+                "egui-wgpu: vertex buffer grown from {} to {} bytes",
+                old_capacity,
+                self.vertex_buffer.capacity
+            );
+            self.vertex_buffer.buffer = create_vertex_buffer(
+                device,
+                &self.descriptor.label_prefix,
+                self.vertex_buffer.capacity,
+            );
         }
+    }
 
+    /// Call the prepare callbacks of `paint_jobs`, and collect their command buffers.
+    fn prepare_callbacks(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        paint_jobs: &[epaint::ClippedPrimitive],
+    ) -> Vec<wgpu::CommandBuffer> {
+        let mut user_cmd_bufs = Vec::new(); // collect user command buffers
+        for epaint::ClippedPrimitive { primitive, .. } in paint_jobs {
+            if let Primitive::Callback(callback) = primitive {
+                let cbfn = if let Some(c) = callback.callback.downcast_ref::<CallbackFn>() {
+                    c
+                } else {
+                    tracing::warn!("Unknown paint callback: expected `egui_wgpu::CallbackFn`");
+                    continue;
+                };
+
+                crate::profile_scope!("callback");
+                user_cmd_bufs.extend((cbfn.prepare)(
+                    device,
+                    queue,
+                    encoder,
+                    &mut self.paint_callback_resources,
+                ));
+            }
+        }
         user_cmd_bufs
     }
 }
 
+/// The label of a wgpu object named `name`, after the [`RendererDescriptor::label_prefix`].
+fn label(label_prefix: &str, name: &str) -> String {
+    format!("{}{}", label_prefix, name)
+}
+
+fn debug_group_label(primitive_index: usize, clip_rect: &epaint::Rect) -> String {
+    format!("egui/primitive[{}] {:?}", primitive_index, clip_rect)
+}
+
+/// The index data to upload for a mesh with `vertex_count` vertices, and its format.
+///
+/// Meshes that can be indexed with `u16` use it to halve the size of their index data.
+/// Those indices are converted into `scratch`, and padded to a multiple of
+/// [`wgpu::COPY_BUFFER_ALIGNMENT`] as required by [`wgpu::Queue::write_buffer`].
+fn index_data<'a>(
+    indices: &'a [u32],
+    vertex_count: usize,
+    scratch: &'a mut Vec<u16>,
+) -> (&'a [u8], wgpu::IndexFormat) {
+    if vertex_count <= u16::MAX as usize {
+        scratch.clear();
+        scratch.extend(indices.iter().map(|&index| index as u16));
+        if scratch.len() % 2 == 1 {
+            scratch.push(0);
+        }
+        (bytemuck::cast_slice(scratch), wgpu::IndexFormat::Uint16)
+    } else {
+        (bytemuck::cast_slice(indices), wgpu::IndexFormat::Uint32)
+    }
+}
+
+/// The total number of vertices and indices of the meshes in `paint_jobs`.
+fn count_vertices_indices(paint_jobs: &[epaint::ClippedPrimitive]) -> (usize, usize) {
+    crate::profile_function!();
+    paint_jobs.iter().fold((0, 0), |acc, clipped_primitive| {
+        match &clipped_primitive.primitive {
+            Primitive::Mesh(mesh) => (acc.0 + mesh.vertices.len(), acc.1 + mesh.indices.len()),
+            Primitive::Callback(_) => acc,
+        }
+    })
+}
+
+/// Hash the vertex and index data of all meshes in `paint_jobs`.
+fn hash_geometry(paint_jobs: &[epaint::ClippedPrimitive]) -> u64 {
+    use std::hash::{BuildHasher as _, Hash as _, Hasher as _};
+
+    let mut hasher = epaint::ahash::RandomState::with_seeds(1, 2, 3, 4).build_hasher();
+    for epaint::ClippedPrimitive { primitive, .. } in paint_jobs {
+        if let Primitive::Mesh(mesh) = primitive {
+            bytemuck::cast_slice::<u32, u8>(&mesh.indices).hash(&mut hasher);
+            bytemuck::cast_slice::<Vertex, u8>(&mesh.vertices).hash(&mut hasher);
+        }
+    }
+    hasher.finish()
+}
+
 fn create_sampler(
     options: epaint::textures::TextureOptions,
     device: &wgpu::Device,
+    label_prefix: &str,
 ) -> wgpu::Sampler {
     let mag_filter = match options.magnification {
         epaint::textures::TextureFilter::Nearest => wgpu::FilterMode::Nearest,
@@ -857,8 +1901,8 @@ fn create_sampler(
     };
     device.create_sampler(&wgpu::SamplerDescriptor {
         label: Some(&format!(
-            "egui sampler (mag: {:?}, min {:?})",
-            mag_filter, min_filter
+            "{}egui sampler (mag: {:?}, min {:?})",
+            label_prefix, mag_filter, min_filter
         )),
         mag_filter,
         min_filter,
@@ -866,20 +1910,102 @@ fn create_sampler(
     })
 }
 
-fn create_vertex_buffer(device: &wgpu::Device, size: u64) -> wgpu::Buffer {
+fn create_pipeline(
+    device: &wgpu::Device,
+    module: &wgpu::ShaderModule,
+    pipeline_layout: &wgpu::PipelineLayout,
+    descriptor: &RendererDescriptor,
+    polygon_mode: wgpu::PolygonMode,
+) -> wgpu::RenderPipeline {
+    let RendererDescriptor {
+        output_color_format,
+        output_depth_format,
+        msaa_samples,
+        ..
+    } = *descriptor;
+
+    let depth_stencil = output_depth_format.map(|format| wgpu::DepthStencilState {
+        format,
+        depth_write_enabled: false,
+        depth_compare: wgpu::CompareFunction::Always,
+        stencil: wgpu::StencilState::default(),
+        bias: wgpu::DepthBiasState::default(),
+    });
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(&label(&descriptor.label_prefix, "egui_pipeline")),
+        layout: Some(pipeline_layout),
+        vertex: wgpu::VertexState {
+            entry_point: "vs_main",
+            module,
+            buffers: &[wgpu::VertexBufferLayout {
+                array_stride: 5 * 4,
+                step_mode: wgpu::VertexStepMode::Vertex,
+                // 0: vec2 position
+                // 1: vec2 texture coordinates
+                // 2: uint color
+                attributes: &wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x2, 2 => Uint32],
+            }],
+        },
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            unclipped_depth: false,
+            conservative: false,
+            cull_mode: None,
+            front_face: wgpu::FrontFace::default(),
+            polygon_mode,
+            strip_index_format: None,
+        },
+        depth_stencil,
+        multisample: wgpu::MultisampleState {
+            alpha_to_coverage_enabled: false,
+            count: msaa_samples,
+            mask: !0,
+        },
+
+        fragment: Some(wgpu::FragmentState {
+            module,
+            entry_point: if output_color_format.describe().srgb {
+                tracing::warn!("Detected a linear (sRGBA aware) framebuffer {:?}. egui prefers Rgba8Unorm or Bgra8Unorm", output_color_format);
+                "fs_main_linear_framebuffer"
+            } else {
+                "fs_main_gamma_framebuffer" // this is what we prefer
+            },
+            targets: &[Some(wgpu::ColorTargetState {
+                format: output_color_format,
+                blend: Some(wgpu::BlendState {
+                    color: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::One,
+                        dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                    alpha: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::OneMinusDstAlpha,
+                        dst_factor: wgpu::BlendFactor::One,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                }),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        multiview: None,
+    })
+}
+
+fn create_vertex_buffer(device: &wgpu::Device, label_prefix: &str, size: u64) -> wgpu::Buffer {
     crate::profile_function!();
     device.create_buffer(&wgpu::BufferDescriptor {
-        label: Some("egui_vertex_buffer"),
+        label: Some(&label(label_prefix, "egui_vertex_buffer")),
         usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
         size,
         mapped_at_creation: false,
     })
 }
 
-fn create_index_buffer(device: &wgpu::Device, size: u64) -> wgpu::Buffer {
+fn create_index_buffer(device: &wgpu::Device, label_prefix: &str, size: u64) -> wgpu::Buffer {
     crate::profile_function!();
     device.create_buffer(&wgpu::BufferDescriptor {
-        label: Some("egui_index_buffer"),
+        label: Some(&label(label_prefix, "egui_index_buffer")),
         usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_DST,
         size,
         mapped_at_creation: false,
@@ -896,6 +2022,11 @@ struct ScissorRect {
 
 impl ScissorRect {
     fn new(clip_rect: &epaint::Rect, pixels_per_point: f32, target_size: [u32; 2]) -> Self {
+        Self::new_unclamped(clip_rect, pixels_per_point).clamp_to(target_size)
+    }
+
+    /// The clip rect in physical pixels, which may extend outside of the target.
+    fn new_unclamped(clip_rect: &epaint::Rect, pixels_per_point: f32) -> Self {
         // Transform clip rect to physical pixels:
         let clip_min_x = pixels_per_point * clip_rect.min.x;
         let clip_min_y = pixels_per_point * clip_rect.min.y;
@@ -908,11 +2039,33 @@ impl ScissorRect {
         let clip_max_x = clip_max_x.round() as u32;
         let clip_max_y = clip_max_y.round() as u32;
 
-        // Clamp:
-        let clip_min_x = clip_min_x.clamp(0, target_size[0]);
-        let clip_min_y = clip_min_y.clamp(0, target_size[1]);
-        let clip_max_x = clip_max_x.clamp(clip_min_x, target_size[0]);
-        let clip_max_y = clip_max_y.clamp(clip_min_y, target_size[1]);
+        Self {
+            x: clip_min_x,
+            y: clip_min_y,
+            width: clip_max_x.saturating_sub(clip_min_x),
+            height: clip_max_y.saturating_sub(clip_min_y),
+        }
+    }
+
+    /// The rect in points, relative to the origin of the target like `clip_rect`.
+    fn to_points(&self, pixels_per_point: f32) -> epaint::Rect {
+        epaint::Rect::from_min_size(
+            (epaint::vec2(self.x as f32, self.y as f32) / pixels_per_point).to_pos2(),
+            epaint::vec2(self.width as f32, self.height as f32) / pixels_per_point,
+        )
+    }
+
+    fn clamp_to(self, target_size: [u32; 2]) -> Self {
+        let clip_min_x = self.x.clamp(0, target_size[0]);
+        let clip_min_y = self.y.clamp(0, target_size[1]);
+        let clip_max_x = self
+            .x
+            .saturating_add(self.width)
+            .clamp(clip_min_x, target_size[0]);
+        let clip_max_y = self
+            .y
+            .saturating_add(self.height)
+            .clamp(clip_min_y, target_size[1]);
 
         Self {
             x: clip_min_x,
@@ -928,3 +2081,234 @@ fn renderer_impl_send_sync() {
     fn assert_send_sync<T: Send + Sync>() {}
     assert_send_sync::<Renderer>();
 }
+
+#[test]
+fn hash_geometry_detects_changes() {
+    let clipped_mesh = |mesh: epaint::Mesh| epaint::ClippedPrimitive {
+        clip_rect: epaint::Rect::EVERYTHING,
+        primitive: Primitive::Mesh(mesh),
+    };
+
+    let mut mesh = epaint::Mesh::default();
+    mesh.add_colored_rect(
+        epaint::Rect::from_min_max(epaint::pos2(0.0, 0.0), epaint::pos2(10.0, 10.0)),
+        epaint::Color32::RED,
+    );
+    let hash = hash_geometry(&[clipped_mesh(mesh.clone())]);
+    assert_eq!(hash, hash_geometry(&[clipped_mesh(mesh.clone())]));
+
+    mesh.vertices[0].pos.x += 1.0;
+    assert_ne!(hash, hash_geometry(&[clipped_mesh(mesh)]));
+}
+
+#[test]
+fn screen_descriptor_hash_and_eq() {
+    let descriptor = ScreenDescriptor {
+        size_in_pixels: [800, 600],
+        pixels_per_point: 2.0,
+    };
+    let same = ScreenDescriptor {
+        size_in_pixels: [800, 600],
+        pixels_per_point: 2.0,
+    };
+    assert!(descriptor == same);
+    assert_eq!(epaint::util::hash(descriptor), epaint::util::hash(same));
+
+    let resized = ScreenDescriptor {
+        size_in_pixels: [800, 601],
+        pixels_per_point: 2.0,
+    };
+    assert!(descriptor != resized);
+    assert_ne!(epaint::util::hash(descriptor), epaint::util::hash(resized));
+
+    let rescaled = ScreenDescriptor {
+        size_in_pixels: [800, 600],
+        pixels_per_point: 1.5,
+    };
+    assert!(descriptor != rescaled);
+    assert_ne!(epaint::util::hash(descriptor), epaint::util::hash(rescaled));
+}
+
+#[test]
+fn scissor_rect_is_scaled_and_clamped() {
+    let rect = epaint::Rect::from_min_max(epaint::pos2(10.0, 20.0), epaint::pos2(30.0, 40.0));
+    let scissor = ScissorRect::new(&rect, 2.0, [1000, 1000]);
+    assert_eq!(
+        (scissor.x, scissor.y, scissor.width, scissor.height),
+        (20, 40, 40, 40)
+    );
+
+    // Clamped to the target:
+    let scissor = ScissorRect::new(&epaint::Rect::EVERYTHING, 1.5, [800, 600]);
+    assert_eq!(
+        (scissor.x, scissor.y, scissor.width, scissor.height),
+        (0, 0, 800, 600)
+    );
+
+    // Entirely outside the target:
+    let rect = epaint::Rect::from_min_max(epaint::pos2(900.0, 0.0), epaint::pos2(950.0, 10.0));
+    let scissor = ScissorRect::new(&rect, 1.0, [800, 600]);
+    assert_eq!((scissor.width, scissor.height), (0, 10));
+}
+
+#[test]
+fn screen_descriptor_scale_rect() {
+    let screen_descriptor = ScreenDescriptor {
+        size_in_pixels: [100, 50],
+        pixels_per_point: 2.0,
+    };
+    let rect = epaint::Rect::from_min_max(epaint::pos2(10.0, 5.0), epaint::pos2(80.0, 20.0));
+    assert_eq!(screen_descriptor.scale_rect(rect), [20, 10, 80, 30]);
+}
+
+#[test]
+fn unclamped_scissor_rect_may_exceed_target() {
+    let rect = epaint::Rect::from_min_max(epaint::pos2(700.0, 500.0), epaint::pos2(850.0, 650.0));
+    let scissor = ScissorRect::new_unclamped(&rect, 1.0);
+    assert_eq!(
+        (scissor.x, scissor.y, scissor.width, scissor.height),
+        (700, 500, 150, 150)
+    );
+    let scissor = scissor.clamp_to([800, 600]);
+    assert_eq!(
+        (scissor.x, scissor.y, scissor.width, scissor.height),
+        (700, 500, 100, 100)
+    );
+}
+
+#[test]
+fn small_meshes_use_u16_indices() {
+    let mut scratch = Vec::new();
+
+    let (data, format) = index_data(&[0, 1, 2], 3, &mut scratch);
+    assert_eq!(format, wgpu::IndexFormat::Uint16);
+    assert_eq!(data, &[0, 0, 1, 0, 2, 0, 0, 0]); // Padded to 4 bytes
+    assert_eq!(data.len() as u64 % wgpu::COPY_BUFFER_ALIGNMENT, 0);
+
+    let indices = [0, 1, 70_000];
+    let (data, format) = index_data(&indices, 70_001, &mut scratch);
+    assert_eq!(format, wgpu::IndexFormat::Uint32);
+    assert_eq!(data, bytemuck::cast_slice::<u32, u8>(&indices));
+
+    let max_u16 = u16::MAX as usize;
+    assert_eq!(
+        index_data(&[0], max_u16, &mut scratch).1,
+        wgpu::IndexFormat::Uint16
+    );
+    assert_eq!(
+        index_data(&[0], max_u16 + 1, &mut scratch).1,
+        wgpu::IndexFormat::Uint32
+    );
+}
+
+#[test]
+fn shader_declares_uniform_locals() {
+    // Otherwise the push constant variant of the shader would still use the uniform buffer.
+    assert!(include_str!("egui.wgsl").contains(UNIFORM_LOCALS_DECLARATION));
+}
+
+#[test]
+fn labels_are_prefixed() {
+    assert_eq!(RendererDescriptor::default().label_prefix, "");
+    assert_eq!(label("", "egui_vertex_buffer"), "egui_vertex_buffer");
+    assert_eq!(
+        label("egui[main_window]/", "egui_vertex_buffer"),
+        "egui[main_window]/egui_vertex_buffer"
+    );
+}
+
+#[test]
+fn paint_callbacks_can_draw_indirect() {
+    // Resources created in `prepare`, e.g. with the draw arguments written by a compute pass.
+    struct IndirectDraw {
+        pipeline: wgpu::RenderPipeline,
+        index_buffer: wgpu::Buffer,
+        indirect_buffer: wgpu::Buffer,
+    }
+
+    fn draw_indirect<'pass>(render_pass: &mut wgpu::RenderPass<'pass>, resources: &'pass TypeMap) {
+        if let Some(draw) = resources.get::<IndirectDraw>() {
+            render_pass.set_pipeline(&draw.pipeline);
+            render_pass.set_index_buffer(draw.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            render_pass.draw_indexed_indirect(&draw.indirect_buffer, 0);
+        }
+    }
+
+    // This only has to compile: the render pass handed to the paint callback takes indirect draws.
+    let _callback = CallbackFn::new()
+        .paint(|_info, render_pass, resources| draw_indirect(render_pass, resources));
+}
+
+#[test]
+fn bind_groups_are_counted() {
+    use epaint::{Color32, ColorImage, ImageDelta, TextureId};
+
+    let (_, _, device, queue) = match crate::test_device() {
+        Some(test_device) => test_device,
+        None => return,
+    };
+    let mut renderer = Renderer::new(&device, &RendererDescriptor::default());
+    assert_eq!(renderer.bind_group_count(), 1);
+
+    let image = ColorImage::new([2, 2], Color32::RED);
+    let full = ImageDelta::full(image, Default::default());
+    renderer
+        .update_texture(&device, &queue, TextureId::Managed(0), &full)
+        .unwrap();
+    assert_eq!(renderer.bind_group_count(), 2);
+
+    // Replacing a texture or updating part of it keeps the count.
+    renderer
+        .update_texture(&device, &queue, TextureId::Managed(0), &full)
+        .unwrap();
+    let partial = ImageDelta::partial(
+        [1, 1],
+        ColorImage::new([1, 1], Color32::BLUE),
+        Default::default(),
+    );
+    renderer
+        .update_texture(&device, &queue, TextureId::Managed(0), &partial)
+        .unwrap();
+    assert_eq!(renderer.bind_group_count(), 2);
+
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: None,
+        size: wgpu::Extent3d {
+            width: 1,
+            height: 1,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Rgba8UnormSrgb,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING,
+    });
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let user_id = renderer.register_native_texture(&device, &view, wgpu::FilterMode::Linear);
+    assert_eq!(renderer.bind_group_count(), 3);
+    renderer.update_egui_texture_from_wgpu_texture(
+        &device,
+        &view,
+        wgpu::FilterMode::Nearest,
+        user_id,
+    );
+    assert_eq!(renderer.bind_group_count(), 3);
+
+    renderer.free_texture(&TextureId::Managed(0));
+    assert_eq!(renderer.bind_group_count(), 2);
+    // Freeing an unknown texture doesn't drop anything.
+    renderer.free_texture(&TextureId::Managed(0));
+    assert_eq!(renderer.bind_group_count(), 2);
+
+    renderer.free_user_textures();
+    assert_eq!(renderer.bind_group_count(), 1);
+
+    renderer
+        .update_texture(&device, &queue, TextureId::Managed(1), &full)
+        .unwrap();
+    renderer.register_native_texture(&device, &view, wgpu::FilterMode::Linear);
+    assert_eq!(renderer.bind_group_count(), 3);
+    renderer.free_all_resources();
+    assert_eq!(renderer.bind_group_count(), 1);
+}
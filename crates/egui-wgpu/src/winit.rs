@@ -1,37 +1,172 @@
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
 
-use tracing::error;
+use tracing::{error, info, warn};
 use wgpu::{Adapter, Instance, Surface};
+use winit::window::WindowId;
 
 use epaint::mutex::RwLock;
 
-use crate::{renderer, RenderState, Renderer, SurfaceErrorAction, WgpuConfiguration};
+use crate::{
+    gpu_timer::GpuTimer, renderer, FrameStats, PainterError, RenderState, Renderer,
+    RendererDescriptor, SurfaceErrorAction, WgpuConfiguration,
+};
 
+/// How many times acquiring the surface texture is retried after a [`wgpu::SurfaceError::Timeout`].
+const SURFACE_TIMEOUT_RETRIES: u32 = 3;
+
+/// The time to wait before the first retry. Doubled for each following retry.
+const SURFACE_TIMEOUT_INITIAL_BACKOFF: std::time::Duration = std::time::Duration::from_millis(1);
+
+/// See [`Painter::set_frame_encoder_label`].
+const DEFAULT_FRAME_ENCODER_LABEL: &str = "egui frame encoder";
+
+#[derive(Debug)]
 struct SurfaceState {
     surface: Surface,
     width: u32,
     height: u32,
+
+    /// HiDPI scale factor (pixels per point) of the window this surface belongs to.
+    pixels_per_point: f32,
+
+    /// The multisampled color target egui is painted to and resolved from into the surface
+    /// texture, if the painter uses more than one MSAA sample.
+    msaa_texture_view: Option<wgpu::TextureView>,
+
+    depth_texture_view: Option<wgpu::TextureView>,
+    /// The depth texture was provided by the user via [`Painter::set_depth_texture`].
+    external_depth_texture: bool,
+
+    /// The surface was lost or outdated, and has not been configured again since.
+    needs_reconfigure: bool,
+
+    /// The last painted frame, while it is waiting for [`Painter::present`].
+    /// See [`Painter::set_manual_present`].
+    current_frame: Option<wgpu::SurfaceTexture>,
+}
+
+/// Texture that egui is painted to by a painter created with [`Painter::new_with_format`].
+#[derive(Debug)]
+struct OffscreenTarget {
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    width: u32,
+    height: u32,
+}
+
+/// Whether [`Painter::paint_offscreen_and_update_textures`] submits its commands to the queue.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SubmissionMode {
+    /// Submit the commands right away.
+    #[default]
+    Immediate,
+
+    /// Keep the command buffer of egui, to be taken with [`Painter::take_command_buffer`]
+    /// and submitted together with those of the application.
+    ///
+    /// The command buffer reads the vertex, index and uniform buffers of the [`Renderer`], which
+    /// are shared by all windows and overwritten by every paint. Submit it before the next paint
+    /// on any window, or the frame will be drawn with the geometry of that paint.
+    ///
+    /// GPU timings are not measured in this mode.
+    Deferred,
+}
+
+/// What [`Painter::paint_to_target`] paints to.
+enum PaintTarget<'a> {
+    /// The given view of the current texture of the surface of a window.
+    Surface {
+        window_id: WindowId,
+        view: &'a wgpu::TextureView,
+    },
+
+    /// The [`OffscreenTarget`].
+    Offscreen { pixels_per_point: f32 },
 }
 
+type FirstFrameCallback = dyn FnOnce(&RenderState);
+
 /// Everything you need to paint egui with [`wgpu`] on [`winit`].
 ///
+/// A single [`Painter`] can paint to any number of windows, which are identified by
+/// their [`WindowId`]. All windows share the same device and [`Renderer`] (and thus textures).
+///
 /// Alternatively you can use [`crate::renderer`] directly.
 pub struct Painter {
     configuration: WgpuConfiguration,
     msaa_samples: u32,
     depth_format: Option<wgpu::TextureFormat>,
-    depth_texture_view: Option<wgpu::TextureView>,
 
-    instance: Instance,
+    /// Created lazily, unless provided via [`Painter::with_instance`].
+    instance: Option<Arc<Instance>>,
     adapter: Option<Adapter>,
     render_state: Option<RenderState>,
-    surface_state: Option<SurfaceState>,
+    /// Set by the uncaptured error handler of a device created by the painter.
+    /// See [`Painter::is_device_lost`].
+    device_lost: Arc<AtomicBool>,
+    surfaces: HashMap<WindowId, SurfaceState>,
+    offscreen_target: Option<OffscreenTarget>,
+
+    /// Only set if the device supports [`wgpu::Features::TIMESTAMP_QUERY`].
+    gpu_timer: Option<GpuTimer>,
+
+    /// See [`Painter::set_texture_updates_enabled`].
+    texture_updates_enabled: bool,
+
+    /// See [`Painter::set_scissor_clamp`].
+    scissor_clamp: bool,
+
+    /// See [`Painter::set_label_prefix`].
+    label_prefix: String,
+    /// See [`Painter::set_frame_encoder_label`].
+    frame_encoder_label: &'static str,
+
+    /// The configuration of the surfaces, apart from their format and size.
+    /// See [`Painter::configure_surface`] and [`Painter::enable_screenshot_capture`].
+    surface_configuration: wgpu::SurfaceConfiguration,
+
+    /// See [`Painter::set_target_region`].
+    target_region: Option<[u32; 4]>,
+
+    /// See [`Painter::set_submission_mode`].
+    submission_mode: SubmissionMode,
+    /// The command buffer of the last frame painted in [`SubmissionMode::Deferred`].
+    deferred_command_buffer: Option<wgpu::CommandBuffer>,
+
+    /// See [`Painter::set_manual_present`].
+    manual_present: bool,
+
+    /// See [`Painter::with_surface_format_override`].
+    surface_format_override: Option<wgpu::TextureFormat>,
+
+    /// See [`Painter::on_first_frame`]. `None` once they have been called.
+    first_frame_callbacks: Option<Vec<Box<FirstFrameCallback>>>,
+}
+
+impl std::fmt::Debug for Painter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Painter")
+            .field("adapter", &self.adapter.as_ref().map(Adapter::get_info))
+            .field("render_state", &self.render_state)
+            .field("msaa_samples", &self.msaa_samples)
+            .field("depth_format", &self.depth_format)
+            .field("surfaces", &self.surfaces)
+            .field("offscreen_target", &self.offscreen_target)
+            .finish_non_exhaustive()
+    }
 }
 
 impl Painter {
     /// Manages [`wgpu`] state, including surface state, required to render egui.
     ///
-    /// Only the [`wgpu::Instance`] is initialized here. Device selection and the initialization
+    /// Creation of the [`wgpu::Instance`] (unless one is provided via
+    /// [`with_instance()`](Self::with_instance)), device selection and the initialization
     /// of render + surface state is deferred until the painter is given its first window target
     /// via [`set_window()`](Self::set_window). (Ensuring that a device that's compatible with the
     /// native window is chosen)
@@ -41,20 +176,211 @@ impl Painter {
     /// [`set_window()`](Self::set_window) once you have
     /// a [`winit::window::Window`] with a valid `.raw_window_handle()`
     /// associated.
+    ///
+    /// With more than one `msaa_samples`, egui is painted to a multisampled texture per window,
+    /// which is resolved into the surface texture.
     pub fn new(configuration: WgpuConfiguration, msaa_samples: u32, depth_bits: u8) -> Self {
-        let instance = wgpu::Instance::new(configuration.backends);
-
         Self {
+            surface_configuration: default_surface_configuration(configuration.present_mode),
             configuration,
             msaa_samples,
             depth_format: (depth_bits > 0).then_some(wgpu::TextureFormat::Depth32Float),
-            depth_texture_view: None,
 
-            instance,
+            instance: None,
             adapter: None,
             render_state: None,
-            surface_state: None,
+            device_lost: Arc::default(),
+            surfaces: HashMap::default(),
+            offscreen_target: None,
+            gpu_timer: None,
+            texture_updates_enabled: true,
+            scissor_clamp: true,
+            label_prefix: String::new(),
+            frame_encoder_label: DEFAULT_FRAME_ENCODER_LABEL,
+            target_region: None,
+            submission_mode: SubmissionMode::Immediate,
+            deferred_command_buffer: None,
+            manual_present: false,
+            surface_format_override: None,
+            first_frame_callbacks: Some(Vec::new()),
+        }
+    }
+
+    /// Like [`Self::new`], but additionally requests `required_features` from the device.
+    ///
+    /// If the adapter selected by [`Self::set_window`] or [`Self::initialize_async`] does not
+    /// support all of them, those return [`PainterError::FeatureNotSupported`] with the missing
+    /// ones, instead of failing later when a pipeline using them is created.
+    pub fn new_with_required_features(
+        mut configuration: WgpuConfiguration,
+        msaa_samples: u32,
+        depth_bits: u8,
+        required_features: wgpu::Features,
+    ) -> Self {
+        configuration.device_descriptor.features |= required_features;
+        Self::new(configuration, msaa_samples, depth_bits)
+    }
+
+    /// Creates a painter that paints to a texture of the given format and size,
+    /// instead of to the surface of a window.
+    ///
+    /// This is meant for offline or server-side rendering, e.g. for video encoding or image export,
+    /// where a specific format such as [`wgpu::TextureFormat::Rgba8Unorm`] or
+    /// [`wgpu::TextureFormat::Rgba16Float`] is required regardless of what a swapchain would give.
+    /// No surface negotiation takes place, and the render state is initialized right away
+    /// using the given device and queue.
+    ///
+    /// Paint with [`Self::paint_offscreen_and_update_textures`], and access the result with
+    /// [`Self::offscreen_texture`].
+    ///
+    /// # Errors
+    /// [`PainterError::UnsupportedFormat`] if `format` is not guaranteed to support being
+    /// rendered to, copied from and sampled.
+    pub fn new_with_format(
+        device: Arc<wgpu::Device>,
+        queue: Arc<wgpu::Queue>,
+        format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> Result<Self, PainterError> {
+        let usage = wgpu::TextureUsages::RENDER_ATTACHMENT
+            | wgpu::TextureUsages::COPY_SRC
+            | wgpu::TextureUsages::TEXTURE_BINDING;
+        if !format
+            .describe()
+            .guaranteed_format_features
+            .allowed_usages
+            .contains(usage)
+        {
+            return Err(PainterError::UnsupportedFormat(format));
+        }
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("egui_offscreen_texture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let renderer = Renderer::new(
+            &device,
+            &RendererDescriptor {
+                output_color_format: format,
+                ..Default::default()
+            },
+        );
+        let gpu_timer = GpuTimer::new(&device, &queue);
+
+        Ok(Self {
+            configuration: WgpuConfiguration::default(),
+            msaa_samples: 1,
+            depth_format: None,
+
+            instance: None,
+            adapter: None,
+            render_state: Some(RenderState {
+                max_texture_side: device.limits().max_texture_dimension_2d as usize,
+                device,
+                queue,
+                target_format: format,
+                renderer: Arc::new(RwLock::new(renderer)),
+            }),
+            device_lost: Arc::default(),
+            surfaces: HashMap::default(),
+            offscreen_target: Some(OffscreenTarget {
+                texture,
+                view,
+                width,
+                height,
+            }),
+            gpu_timer,
+            texture_updates_enabled: true,
+            scissor_clamp: true,
+            label_prefix: String::new(),
+            frame_encoder_label: DEFAULT_FRAME_ENCODER_LABEL,
+            surface_configuration: default_surface_configuration(
+                WgpuConfiguration::default().present_mode,
+            ),
+            target_region: None,
+            submission_mode: SubmissionMode::Immediate,
+            deferred_command_buffer: None,
+            manual_present: false,
+            surface_format_override: None,
+            first_frame_callbacks: None,
+        })
+    }
+
+    /// The texture egui is painted to by a painter created with [`Self::new_with_format`].
+    pub fn offscreen_texture(&self) -> Option<&wgpu::Texture> {
+        self.offscreen_target.as_ref().map(|target| &target.texture)
+    }
+
+    /// Use an existing [`wgpu::Instance`] instead of creating a new one.
+    ///
+    /// This is useful if the application already uses wgpu, e.g. for 3D rendering,
+    /// since having several instances at the same time can cause issues on some platforms.
+    ///
+    /// [`WgpuConfiguration::backends`] is ignored when an instance is provided.
+    /// Must be called before the first call to [`set_window()`](Self::set_window).
+    pub fn with_instance(mut self, instance: Arc<wgpu::Instance>) -> Self {
+        if self.adapter.is_some() {
+            error!("Ignoring wgpu instance provided after the painter has been initialized");
+        } else {
+            self.instance = Some(instance);
+        }
+        self
+    }
+
+    /// The [`wgpu::Instance`] the painter creates its surfaces with.
+    ///
+    /// Use it to create additional surfaces, e.g. for popup windows, that are compatible with
+    /// the device of the painter.
+    /// Returns `None` until the first call to [`Self::set_window`], unless an instance was
+    /// provided with [`Self::with_instance`].
+    pub fn instance(&self) -> Option<&wgpu::Instance> {
+        self.instance.as_deref()
+    }
+
+    /// Run `callback` once the render state is available, right after the first successful
+    /// call to [`Self::set_window`].
+    ///
+    /// Use this for one-time setup that needs the device, such as creating the pipelines of
+    /// [`crate::CallbackFn`]s or registering textures, without checking
+    /// [`Self::render_state`] every frame.
+    /// If the first window has already been set, or the painter was created with
+    /// [`Self::new_with_format`], `callback` is run right away.
+    pub fn on_first_frame(&mut self, callback: impl FnOnce(&RenderState) + 'static) {
+        match (&mut self.first_frame_callbacks, &self.render_state) {
+            (Some(callbacks), _) => callbacks.push(Box::new(callback)),
+            (None, Some(render_state)) => callback(render_state),
+            (None, None) => warn!("Ignoring first frame callback of a destroyed painter"),
+        }
+    }
+
+    /// Use the given format for the surfaces, instead of picking one of the formats they report
+    /// as supported.
+    ///
+    /// This is meant for testing rendering with a specific format, e.g. when a test environment
+    /// reports unexpected formats. Configuring a surface with a format it doesn't actually
+    /// support is a wgpu validation error.
+    /// Must be called before the first call to [`set_window()`](Self::set_window).
+    pub fn with_surface_format_override(mut self, format: wgpu::TextureFormat) -> Self {
+        if self.render_state.is_some() {
+            error!(
+                "Ignoring surface format override provided after the painter has been initialized"
+            );
+        } else {
+            self.surface_format_override = Some(format);
         }
+        self
     }
 
     /// Get the [`RenderState`].
@@ -64,24 +390,97 @@ impl Painter {
         self.render_state.clone()
     }
 
+    /// Replace the [`RenderState`], e.g. with one holding a new [`Renderer`] after a shader
+    /// change, and return the previous one.
+    ///
+    /// `instance` and `adapter` must be those the device of the new render state was created
+    /// with, and replace those of the painter. The surfaces of all windows are marked for
+    /// reconfiguration, and configured again right away with the device and target format of
+    /// the new render state, unless they are zero-sized. Depth textures provided with
+    /// [`Self::set_depth_texture`] belong to the old device, so they are dropped and replaced by
+    /// depth textures of the painter. If `instance` is not [`Self::instance`], the surfaces
+    /// could not be used with the new device, so they are dropped, and [`Self::set_window`]
+    /// has to be called again for each window.
+    /// The texture of a painter created with [`Self::new_with_format`] is not recreated,
+    /// so its render state must keep using the same device.
+    ///
+    /// Textures are not carried over to the new renderer: egui has to upload them again,
+    /// e.g. by painting with the full textures delta of a new `egui::Context`.
+    /// Wait for the GPU to finish using the old render state before dropping it,
+    /// e.g. with `device.poll(wgpu::Maintain::Wait)`.
+    pub fn swap_render_state(
+        &mut self,
+        instance: Arc<Instance>,
+        adapter: Adapter,
+        render_state: RenderState,
+    ) -> Option<RenderState> {
+        crate::profile_function!();
+
+        self.gpu_timer = GpuTimer::new(&render_state.device, &render_state.queue);
+        self.deferred_command_buffer = None;
+        self.device_lost = Arc::default();
+        let old_render_state = self.render_state.replace(render_state);
+        self.adapter = Some(adapter);
+        if !self
+            .instance
+            .as_ref()
+            .map_or(false, |old_instance| Arc::ptr_eq(old_instance, &instance))
+        {
+            self.surfaces.clear();
+        }
+        self.instance = Some(instance);
+
+        for surface_state in self.surfaces.values_mut() {
+            surface_state.needs_reconfigure = true;
+            surface_state.msaa_texture_view = None;
+            surface_state.external_depth_texture = false;
+            surface_state.depth_texture_view = None;
+        }
+
+        let sizes: Vec<_> = self
+            .surfaces
+            .iter()
+            .map(|(window_id, surface_state)| {
+                (*window_id, surface_state.width, surface_state.height)
+            })
+            .collect();
+        for (window_id, width, height) in sizes {
+            if width != 0 && height != 0 {
+                self.resize_and_generate_depth_texture_view(window_id, width, height);
+            }
+        }
+
+        old_render_state
+    }
+
     async fn init_render_state(
         &self,
         adapter: &Adapter,
         target_format: wgpu::TextureFormat,
-    ) -> Result<RenderState, wgpu::RequestDeviceError> {
-        adapter
+    ) -> Result<RenderState, PainterError> {
+        let (device, queue) = adapter
             .request_device(&self.configuration.device_descriptor, None)
-            .await
-            .map(|(device, queue)| {
-                let renderer =
-                    Renderer::new(&device, target_format, self.depth_format, self.msaa_samples);
-                RenderState {
-                    device: Arc::new(device),
-                    queue: Arc::new(queue),
-                    target_format,
-                    renderer: Arc::new(RwLock::new(renderer)),
-                }
-            })
+            .await?;
+        watch_device_lost(&device, self.device_lost.clone());
+        let renderer = Renderer::try_new(
+            &device,
+            &RendererDescriptor {
+                output_color_format: target_format,
+                output_depth_format: self.depth_format,
+                msaa_samples: self.msaa_samples,
+                texture_bind_group_layout: None,
+                screen_uniform_binding: 0,
+                label_prefix: self.label_prefix.clone(),
+            },
+        )
+        .await?;
+        Ok(RenderState {
+            max_texture_side: device.limits().max_texture_dimension_2d as usize,
+            device: Arc::new(device),
+            queue: Arc::new(queue),
+            target_format,
+            renderer: Arc::new(RwLock::new(renderer)),
+        })
     }
 
     // We want to defer the initialization of our render state until we have a surface
@@ -91,63 +490,81 @@ impl Painter {
     // will have the same format and so this render state will remain valid.
     async fn ensure_render_state_for_surface(
         &mut self,
+        instance: &Instance,
         surface: &Surface,
-    ) -> Result<(), wgpu::RequestDeviceError> {
+    ) -> Result<(), PainterError> {
         if self.adapter.is_none() {
-            self.adapter = self
-                .instance
+            self.adapter = instance
                 .request_adapter(&wgpu::RequestAdapterOptions {
                     power_preference: self.configuration.power_preference,
                     compatible_surface: Some(surface),
                     force_fallback_adapter: false,
                 })
                 .await;
+
+            if let Some(adapter) = &self.adapter {
+                let adapter_info = adapter.get_info();
+                info!(
+                    adapter = %adapter_info.name,
+                    backend = ?adapter_info.backend,
+                    "egui-wgpu: initialized"
+                );
+            }
         }
         if self.render_state.is_none() {
             match &self.adapter {
                 Some(adapter) => {
-                    let swapchain_format = crate::preferred_framebuffer_format(
-                        &surface.get_supported_formats(adapter),
-                    );
+                    let missing_features =
+                        self.configuration.device_descriptor.features - adapter.features();
+                    if !missing_features.is_empty() {
+                        return Err(PainterError::FeatureNotSupported(missing_features));
+                    }
+                    let swapchain_format = self.surface_format_override.unwrap_or_else(|| {
+                        crate::preferred_framebuffer_format(&surface.get_supported_formats(adapter))
+                    });
+                    self.device_lost = Arc::default();
                     let rs = self.init_render_state(adapter, swapchain_format).await?;
+                    self.gpu_timer = GpuTimer::new(&rs.device, &rs.queue);
                     self.render_state = Some(rs);
                 }
-                None => return Err(wgpu::RequestDeviceError {}),
+                None => return Err(PainterError::NoSuitableAdapter),
             }
         }
         Ok(())
     }
 
-    fn configure_surface(&mut self, width_in_pixels: u32, height_in_pixels: u32) {
-        crate::profile_function!();
-
-        let render_state = self
-            .render_state
-            .as_ref()
-            .expect("Render state should exist before surface configuration");
-        let format = render_state.target_format;
-
-        let config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format,
-            width: width_in_pixels,
-            height: height_in_pixels,
-            present_mode: self.configuration.present_mode,
-            alpha_mode: wgpu::CompositeAlphaMode::Auto,
-        };
-
-        let surface_state = self
-            .surface_state
-            .as_mut()
-            .expect("Surface state should exist before surface configuration");
-        surface_state
-            .surface
-            .configure(&render_state.device, &config);
-        surface_state.width = width_in_pixels;
-        surface_state.height = height_in_pixels;
+    /// Select an adapter compatible with `surface` and create the device and render state,
+    /// without blocking.
+    ///
+    /// This lets async applications initialize the painter before their first window is
+    /// passed to [`Self::set_window`], which then reuses the device.
+    /// `surface` must be created from `instance`, which the painter keeps and uses for the
+    /// surfaces of all windows, replacing one provided with [`Self::with_instance`].
+    /// Does nothing if the painter is already initialized.
+    ///
+    /// # Errors
+    /// If the provided wgpu configuration does not match an available adapter or device.
+    pub async fn initialize_async(
+        &mut self,
+        instance: Arc<Instance>,
+        surface: &Surface,
+    ) -> Result<(), PainterError> {
+        if self.render_state.is_some() {
+            return Ok(());
+        }
+        // No surface has been created yet, so the instance can still be replaced.
+        if let Some(previous) = &self.instance {
+            if !Arc::ptr_eq(previous, &instance) {
+                warn!("Replacing the wgpu instance provided with Painter::with_instance");
+            }
+        }
+        self.instance = Some(instance.clone());
+        self.ensure_render_state_for_surface(&instance, surface)
+            .await
     }
 
-    /// Updates (or clears) the [`winit::window::Window`] associated with the [`Painter`]
+    /// Updates (or clears) the [`winit::window::Window`] with the given [`WindowId`]
+    /// associated with the [`Painter`]
     ///
     /// This creates a [`wgpu::Surface`] for the given Window (as well as initializing render
     /// state if needed) that is used for egui rendering.
@@ -173,216 +590,1549 @@ impl Painter {
     /// be cleared by passing `None`).
     ///
     /// # Errors
-    /// If the provided wgpu configuration does not match an available device.
+    /// If the provided wgpu configuration does not match an available adapter or device.
     pub async unsafe fn set_window(
         &mut self,
+        window_id: WindowId,
         window: Option<&winit::window::Window>,
-    ) -> Result<(), wgpu::RequestDeviceError> {
+    ) -> Result<(), PainterError> {
         match window {
             Some(window) => {
-                let surface = self.instance.create_surface(&window);
+                debug_assert_eq!(window_id, window.id());
+                let backends = self.configuration.backends;
+                let instance = self
+                    .instance
+                    .get_or_insert_with(|| Arc::new(wgpu::Instance::new(backends)))
+                    .clone();
+                let surface = instance.create_surface(&window);
 
-                self.ensure_render_state_for_surface(&surface).await?;
+                self.ensure_render_state_for_surface(&instance, &surface)
+                    .await?;
 
                 let size = window.inner_size();
                 let width = size.width;
                 let height = size.height;
-                self.surface_state = Some(SurfaceState {
-                    surface,
-                    width,
-                    height,
-                });
-                self.resize_and_generate_depth_texture_view(width, height);
+                self.surfaces.insert(
+                    window_id,
+                    SurfaceState {
+                        surface,
+                        width,
+                        height,
+                        pixels_per_point: window.scale_factor() as f32,
+                        msaa_texture_view: None,
+                        depth_texture_view: None,
+                        external_depth_texture: false,
+                        needs_reconfigure: false,
+                        current_frame: None,
+                    },
+                );
+                self.resize_and_generate_depth_texture_view(window_id, width, height);
+
+                if let (Some(callbacks), Some(render_state)) =
+                    (self.first_frame_callbacks.take(), &self.render_state)
+                {
+                    for callback in callbacks {
+                        callback(render_state);
+                    }
+                }
             }
             None => {
-                self.surface_state = None;
+                self.surfaces.remove(&window_id);
             }
         }
         Ok(())
     }
 
-    /// Returns the maximum texture dimension supported if known
+    /// Enable or disable texture uploads for the next painted frame.
     ///
-    /// This API will only return a known dimension after `set_window()` has been called
-    /// at least once, since the underlying device and render state are initialized lazily
-    /// once we have a window (that may determine the choice of adapter/device).
-    pub fn max_texture_side(&self) -> Option<usize> {
-        self.render_state
-            .as_ref()
-            .map(|rs| rs.device.limits().max_texture_dimension_2d as usize)
+    /// This is a debugging aid: while disabled, the `set` of the textures deltas is ignored for
+    /// the textures the renderer already has, which are kept as they are. This helps telling
+    /// whether a visual bug is caused by texture data or by geometry.
+    /// Textures the renderer does not have yet are still uploaded, since later partial updates
+    /// of them would fail otherwise.
+    ///
+    /// To avoid stale textures, uploads are automatically re-enabled after each painted frame,
+    /// so this has to be called before every frame that should skip them.
+    pub fn set_texture_updates_enabled(&mut self, enabled: bool) {
+        self.texture_updates_enabled = enabled;
     }
 
-    fn resize_and_generate_depth_texture_view(
-        &mut self,
-        width_in_pixels: u32,
-        height_in_pixels: u32,
-    ) {
-        self.configure_surface(width_in_pixels, height_in_pixels);
-        let device = &self.render_state.as_ref().unwrap().device;
-        self.depth_texture_view = self.depth_format.map(|depth_format| {
-            device
-                .create_texture(&wgpu::TextureDescriptor {
-                    label: Some("egui_depth_texture"),
-                    size: wgpu::Extent3d {
-                        width: width_in_pixels,
-                        height: height_in_pixels,
-                        depth_or_array_layers: 1,
-                    },
-                    mip_level_count: 1,
-                    sample_count: 1,
-                    dimension: wgpu::TextureDimension::D2,
-                    format: depth_format,
-                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT
-                        | wgpu::TextureUsages::TEXTURE_BINDING,
-                })
-                .create_view(&wgpu::TextureViewDescriptor::default())
-        });
+    /// Whether scissor rects are clamped to the bounds of the surface (default `true`).
+    ///
+    /// See [`Renderer::set_scissor_clamp`]. Only disable this for debugging.
+    pub fn set_scissor_clamp(&mut self, scissor_clamp: bool) {
+        self.scissor_clamp = scissor_clamp;
     }
 
-    pub fn on_window_resized(&mut self, width_in_pixels: u32, height_in_pixels: u32) {
-        if self.surface_state.is_some() {
-            self.resize_and_generate_depth_texture_view(width_in_pixels, height_in_pixels);
-        } else {
-            error!("Ignoring window resize notification with no surface created via Painter::set_window()");
+    /// Prefix the labels of the wgpu objects created by the painter and its renderer,
+    /// e.g. with `"egui[main_window]/"`.
+    ///
+    /// This tells apart the objects of several painters sharing a device in GPU frame captures.
+    /// Objects that already exist keep their labels, so call this before [`Self::set_window`].
+    pub fn set_label_prefix(&mut self, prefix: impl Into<String>) {
+        self.label_prefix = prefix.into();
+        if let Some(render_state) = &self.render_state {
+            render_state
+                .renderer
+                .write()
+                .set_label_prefix(self.label_prefix.clone());
         }
     }
 
-    pub fn paint_and_update_textures(
-        &mut self,
-        pixels_per_point: f32,
-        clear_color: epaint::Rgba,
-        clipped_primitives: &[epaint::ClippedPrimitive],
-        textures_delta: &epaint::textures::TexturesDelta,
-    ) {
-        crate::profile_function!();
-
-        let render_state = match self.render_state.as_mut() {
-            Some(rs) => rs,
-            None => return,
-        };
-        let surface_state = match self.surface_state.as_ref() {
-            Some(rs) => rs,
-            None => return,
-        };
-        let (width, height) = (surface_state.width, surface_state.height);
-
-        let output_frame = {
-            crate::profile_scope!("get_current_texture");
-            // This is what vsync-waiting happens, at least on Mac.
-            surface_state.surface.get_current_texture()
-        };
-
-        let output_frame = match output_frame {
-            Ok(frame) => frame,
-            #[allow(clippy::single_match_else)]
-            Err(e) => match (*self.configuration.on_surface_error)(e) {
-                SurfaceErrorAction::RecreateSurface => {
-                    self.configure_surface(width, height);
-                    return;
-                }
-                SurfaceErrorAction::SkipFrame => {
-                    return;
-                }
-            },
-        };
-
-        let mut encoder =
-            render_state
-                .device
-                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                    label: Some("encoder"),
-                });
-
-        // Upload all resources for the GPU.
-        let screen_descriptor = renderer::ScreenDescriptor {
-            size_in_pixels: [width, height],
-            pixels_per_point,
-        };
+    /// Set the label of the command encoder the painter records each frame with
+    /// (default `"egui frame encoder"`), after the [label prefix](Self::set_label_prefix).
+    pub fn set_frame_encoder_label(&mut self, label: &'static str) {
+        self.frame_encoder_label = label;
+    }
 
-        let user_cmd_bufs = {
-            let mut renderer = render_state.renderer.write();
-            for (id, image_delta) in &textures_delta.set {
-                renderer.update_texture(
-                    &render_state.device,
-                    &render_state.queue,
-                    *id,
-                    image_delta,
-                );
-            }
+    /// Paint egui into the sub-region of the render target with its top-left corner at `x, y`
+    /// and a size of `width` by `height` physical pixels, e.g. next to a toolbar or a 3D view,
+    /// instead of into the whole target.
+    ///
+    /// This sets both the viewport and the scissor rects, so egui's coordinate space is mapped
+    /// to the region. The region applies to all following frames until
+    /// [`Self::clear_target_region`] is called, and is clipped to the target when painting.
+    /// The whole target is still cleared with the `clear_color` of the frame, if any.
+    ///
+    /// Paint callbacks still get the viewport and clip rect relative to the region, with its
+    /// origin in [`epaint::PaintCallbackInfo::target_origin_px`].
+    pub fn set_target_region(&mut self, x: u32, y: u32, width: u32, height: u32) {
+        self.target_region = Some([x, y, width, height]);
+    }
 
-            renderer.update_buffers(
-                &render_state.device,
-                &render_state.queue,
-                &mut encoder,
-                clipped_primitives,
-                &screen_descriptor,
-            )
-        };
+    /// Paint egui into the whole render target again. See [`Self::set_target_region`].
+    pub fn clear_target_region(&mut self) {
+        self.target_region = None;
+    }
 
-        {
-            let renderer = render_state.renderer.read();
-            let frame_view = output_frame
-                .texture
-                .create_view(&wgpu::TextureViewDescriptor::default());
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &frame_view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: clear_color.r() as f64,
-                            g: clear_color.g() as f64,
-                            b: clear_color.b() as f64,
-                            a: clear_color.a() as f64,
-                        }),
-                        store: true,
-                    },
-                })],
-                depth_stencil_attachment: self.depth_texture_view.as_ref().map(|view| {
-                    wgpu::RenderPassDepthStencilAttachment {
-                        view,
-                        depth_ops: Some(wgpu::Operations {
-                            load: wgpu::LoadOp::Clear(1.0),
-                            store: true,
-                        }),
-                        stencil_ops: None,
-                    }
-                }),
-                label: Some("egui_render"),
-            });
+    /// Configure the surfaces with [`wgpu::TextureUsages::COPY_SRC`], so that their textures
+    /// can be copied from, e.g. to capture screenshots.
+    ///
+    /// This only takes effect when a surface is configured, so call this before the window is
+    /// set with [`Self::set_window`], or before resizing it.
+    pub fn enable_screenshot_capture(&mut self) {
+        self.surface_configuration.usage |= wgpu::TextureUsages::COPY_SRC;
+    }
 
-            renderer.render(&mut render_pass, clipped_primitives, &screen_descriptor);
-        }
+    /// Modify the [`wgpu::SurfaceConfiguration`] the surfaces are configured with,
+    /// e.g. to add usage flags or combine a present mode with a specific alpha mode.
+    ///
+    /// The modified configuration is kept and used whenever a surface is configured.
+    /// Its `format`, `width` and `height` are ignored: the surfaces always use the format of
+    /// [`RenderState::target_format`] and the size of their window.
+    /// Surfaces that have already been configured are configured again right away.
+    pub fn configure_surface(&mut self, config: impl FnOnce(&mut wgpu::SurfaceConfiguration)) {
+        config(&mut self.surface_configuration);
 
-        {
-            let mut renderer = render_state.renderer.write();
-            for id in &textures_delta.free {
-                renderer.free_texture(id);
+        if let Some(render_state) = &self.render_state {
+            for surface_state in self.surfaces.values_mut() {
+                let (width, height) = (surface_state.width, surface_state.height);
+                if width != 0 && height != 0 {
+                    configure_surface(
+                        surface_state,
+                        render_state,
+                        &self.surface_configuration,
+                        width,
+                        height,
+                    );
+                }
             }
         }
+    }
 
-        let encoded = {
-            crate::profile_scope!("CommandEncoder::finish");
-            encoder.finish()
-        };
+    /// Set whether [`Self::paint_and_update_textures`] presents the frame it painted (the
+    /// default), or keeps it until [`Self::present`] is called.
+    ///
+    /// This lets a compositor hosting egui access the painted swapchain image with
+    /// [`Self::get_surface_texture`], e.g. to render its own content on top of it.
+    /// To sample or copy it, add the required usages with [`Self::configure_surface`].
+    pub fn set_manual_present(&mut self, manual_present: bool) {
+        self.manual_present = manual_present;
+    }
 
-        // Submit the commands: both the main buffer and user-defined ones.
-        {
-            crate::profile_scope!("Queue::submit");
-            render_state
-                .queue
-                .submit(user_cmd_bufs.into_iter().chain(std::iter::once(encoded)));
-        };
+    /// The surface texture the last frame was painted to, while it is waiting for
+    /// [`Self::present`]. Only available with [`Self::set_manual_present`].
+    pub fn get_surface_texture(&self, window_id: WindowId) -> Option<&wgpu::SurfaceTexture> {
+        self.surfaces
+            .get(&window_id)
+            .and_then(|surface_state| surface_state.current_frame.as_ref())
+    }
 
-        // Redraw egui
+    /// Present the frame last painted to the given window with [`Self::set_manual_present`].
+    ///
+    /// A frame that is still waiting when the next one is painted is presented then,
+    /// since the surface doesn't hand out a new texture before the old one is released.
+    /// Does nothing if no frame is waiting.
+    pub fn present(&mut self, window_id: WindowId) {
+        if let Some(frame) = self
+            .surfaces
+            .get_mut(&window_id)
+            .and_then(|surface_state| surface_state.current_frame.take())
         {
             crate::profile_scope!("present");
-            output_frame.present();
+            frame.present();
         }
     }
 
-    #[allow(clippy::unused_self)]
-    pub fn destroy(&mut self) {
-        // TODO(emilk): something here?
+    /// Set whether [`Self::paint_offscreen_and_update_textures`] submits its commands to the
+    /// queue (the default), or keeps them for [`Self::take_command_buffer`].
+    ///
+    /// This does not affect [`Self::paint_and_update_textures`], which always submits,
+    /// since the surface texture can only be presented once its commands have been submitted.
+    pub fn set_submission_mode(&mut self, mode: SubmissionMode) {
+        self.submission_mode = mode;
+    }
+
+    /// Take the command buffer of the last frame painted in [`SubmissionMode::Deferred`],
+    /// e.g. to submit it in the same [`wgpu::Queue::submit`] as the command buffers of the
+    /// application.
+    ///
+    /// The command buffers returned by the prepare stage of [`crate::CallbackFn`]s are still
+    /// submitted while painting, so they are executed before this one.
+    ///
+    /// The command buffer must be submitted before the next paint on any window,
+    /// see [`SubmissionMode::Deferred`].
+    pub fn take_command_buffer(&mut self) -> Option<wgpu::CommandBuffer> {
+        self.deferred_command_buffer.take()
     }
+
+    /// Upload a texture right away, instead of with the [`epaint::textures::TexturesDelta`]
+    /// of the frame that first uses it.
+    ///
+    /// This can be used to preload icons or backgrounds during initialization, so that the
+    /// first frame showing them doesn't stutter. Requires an initialized render state
+    /// (see [`Self::set_window`]). An existing texture with the same `id` is replaced.
+    ///
+    /// # Errors
+    /// [`PainterError::NotInitialized`] before the render state exists, or
+    /// [`PainterError::TextureTooLarge`].
+    pub fn preload_texture(
+        &mut self,
+        id: epaint::TextureId,
+        image: epaint::ColorImage,
+        options: epaint::textures::TextureOptions,
+    ) -> Result<(), PainterError> {
+        crate::profile_function!();
+
+        let render_state = self
+            .render_state
+            .as_ref()
+            .ok_or(PainterError::NotInitialized)?;
+        render_state.renderer.write().update_texture(
+            &render_state.device,
+            &render_state.queue,
+            id,
+            &epaint::ImageDelta::full(image, options),
+        )?;
+        // The upload is only executed with the next submission, which may be a while.
+        render_state.queue.submit(std::iter::empty());
+        Ok(())
+    }
+
+    /// The number of windows that currently have a surface, i.e. that have been passed to
+    /// [`Self::set_window`] and not been removed since.
+    pub fn surface_count(&self) -> usize {
+        self.surfaces.len()
+    }
+
+    /// Returns the maximum texture dimension supported if known
+    ///
+    /// This API will only return a known dimension after `set_window()` has been called
+    /// at least once, since the underlying device and render state are initialized lazily
+    /// once we have a window (that may determine the choice of adapter/device).
+    pub fn max_texture_side(&self) -> Option<usize> {
+        self.render_state.as_ref().map(|rs| rs.max_texture_side)
+    }
+
+    /// Whether the device supports drawing egui as wireframes with [`Self::set_wireframe`].
+    ///
+    /// This requires [`wgpu::Features::POLYGON_MODE_LINE`], which is only enabled on the device
+    /// if it was requested, e.g. with [`Self::new_with_required_features`].
+    /// Returns `false` before the painter has been initialized.
+    pub fn supports_wireframe(&self) -> bool {
+        self.render_state.as_ref().map_or(false, |render_state| {
+            render_state
+                .device
+                .features()
+                .contains(wgpu::Features::POLYGON_MODE_LINE)
+        })
+    }
+
+    /// Recreate the render pipeline of the [`Renderer`] on `device` right away, applying
+    /// pending changes such as [`Self::set_wireframe`], instead of when the next frame is painted.
+    ///
+    /// This separates changing settings from applying them, e.g. to keep the cost of pipeline
+    /// creation out of a frame. `device` must be the device of [`Self::render_state`].
+    /// Does nothing before the painter has been initialized.
+    pub fn force_pipeline_recreation(&mut self, device: &wgpu::Device) {
+        if let Some(render_state) = &self.render_state {
+            render_state.renderer.write().recreate_pipeline(device);
+        }
+    }
+
+    /// Draw the triangles of egui meshes as wireframes, e.g. to debug tessellation.
+    ///
+    /// Takes effect from the next painted frame.
+    ///
+    /// # Errors
+    /// [`PainterError::NotInitialized`] before the painter has been initialized, and
+    /// [`PainterError::FeatureNotSupported`] when enabling wireframes on a device that doesn't
+    /// [support](Self::supports_wireframe) them.
+    pub fn set_wireframe(&mut self, wireframe: bool) -> Result<(), PainterError> {
+        let render_state = self
+            .render_state
+            .as_ref()
+            .ok_or(PainterError::NotInitialized)?;
+        if wireframe && !self.supports_wireframe() {
+            return Err(PainterError::FeatureNotSupported(
+                wgpu::Features::POLYGON_MODE_LINE,
+            ));
+        }
+        render_state
+            .renderer
+            .write()
+            .set_polygon_mode(if wireframe {
+                wgpu::PolygonMode::Line
+            } else {
+                wgpu::PolygonMode::Fill
+            });
+        Ok(())
+    }
+
+    /// The present mode the surfaces are configured with.
+    ///
+    /// [`wgpu::PresentMode::AutoVsync`] and [`wgpu::PresentMode::AutoNoVsync`] are resolved to
+    /// the mode wgpu picks for them, which depends on what the surfaces support.
+    /// Returns `None` before the first window has been set with [`Self::set_window`].
+    pub fn present_mode(&self) -> Option<wgpu::PresentMode> {
+        let adapter = self.adapter.as_ref()?;
+        let surface_state = self.surfaces.values().next()?;
+        resolve_present_mode(
+            self.surface_configuration.present_mode,
+            &surface_state.surface.get_supported_present_modes(adapter),
+        )
+    }
+
+    /// Whether the surfaces have an sRGB format, i.e. one that converts the linear colors
+    /// written by a shader to gamma space when storing them.
+    ///
+    /// egui already accounts for this by picking the matching fragment shader entry point, but
+    /// [`crate::CallbackFn`]s doing color-correct rendering need to know whether to convert
+    /// their colors themselves.
+    /// Returns `false` before the painter has been initialized.
+    pub fn is_srgb_surface(&self) -> bool {
+        self.render_state.as_ref().map_or(false, |render_state| {
+            render_state.target_format.describe().srgb
+        })
+    }
+
+    /// The depth format of the egui render pass, or `None` if depth is disabled.
+    ///
+    /// [`crate::CallbackFn`]s that depth-test must create their pipelines with this format.
+    pub fn depth_format(&self) -> Option<wgpu::TextureFormat> {
+        self.depth_format
+    }
+
+    /// Set the depth format of the egui render pass, overriding the `depth_bits` the painter was
+    /// created with. `None` disables depth.
+    ///
+    /// Use this to match a depth texture that is provided with [`Self::set_depth_texture`].
+    /// The depth format is baked into the render pipeline, so this must be called before the
+    /// render state is initialized by [`Self::set_window`].
+    ///
+    /// # Errors
+    /// [`PainterError::UnsupportedFormat`] if `format` is not a depth format, or if the render
+    /// state has already been initialized with another depth format.
+    pub fn set_depth_format(
+        &mut self,
+        format: Option<wgpu::TextureFormat>,
+    ) -> Result<(), PainterError> {
+        if let Some(format) = format {
+            if format.describe().sample_type != wgpu::TextureSampleType::Depth {
+                return Err(PainterError::UnsupportedFormat(format));
+            }
+        }
+        if self.render_state.is_some() && self.depth_format != format {
+            // At least one of them is `Some`, since they differ.
+            if let Some(format) = format.or(self.depth_format) {
+                return Err(PainterError::UnsupportedFormat(format));
+            }
+        }
+        self.depth_format = format;
+        Ok(())
+    }
+
+    /// Give the depth buffer of the egui render pass a stencil aspect, e.g.
+    /// [`wgpu::TextureFormat::Depth24PlusStencil8`], so that [`crate::CallbackFn`]s can use
+    /// stencil tests. `None` removes the stencil aspect again, keeping the depth aspect.
+    ///
+    /// The stencil buffer is cleared to zero at the start of the egui render pass, unless a
+    /// depth texture was provided with [`Self::set_depth_texture`].
+    /// egui itself doesn't use the stencil buffer: its clip rects are axis-aligned rectangles,
+    /// which scissor rects already clip exactly.
+    ///
+    /// Like the depth format, this is baked into the render pipeline, so it must be called before
+    /// the render state is initialized by [`Self::set_window`].
+    ///
+    /// # Errors
+    /// [`PainterError::UnsupportedFormat`] if `format` has no stencil aspect, or if the render
+    /// state has already been initialized with another depth format.
+    pub fn set_stencil_format(
+        &mut self,
+        format: Option<wgpu::TextureFormat>,
+    ) -> Result<(), PainterError> {
+        let depth_format = match format {
+            Some(format) if !has_stencil_aspect(format) => {
+                return Err(PainterError::UnsupportedFormat(format));
+            }
+            Some(format) => Some(format),
+            None => self.depth_format.map(|format| match format {
+                wgpu::TextureFormat::Depth24PlusStencil8 => wgpu::TextureFormat::Depth24Plus,
+                wgpu::TextureFormat::Depth32FloatStencil8 => wgpu::TextureFormat::Depth32Float,
+                format => format,
+            }),
+        };
+        if let Some(depth_format) = depth_format {
+            if self.render_state.is_some() && self.depth_format != Some(depth_format) {
+                return Err(PainterError::UnsupportedFormat(depth_format));
+            }
+        }
+        self.depth_format = depth_format;
+        Ok(())
+    }
+
+    /// Whether the device created by the painter has been lost.
+    ///
+    /// Once it is, all `paint_*` methods return [`PainterError::DeviceLost`], and the painter
+    /// must be recreated. Only devices the painter requested itself are watched, since watching
+    /// a device replaces its uncaptured error handler. For those, any other uncaptured error panics,
+    /// like it does with the default handler of wgpu.
+    pub fn is_device_lost(&self) -> bool {
+        self.device_lost.load(Ordering::Relaxed)
+    }
+
+    /// Whether [`Self::paint_and_update_textures`] can currently paint to the given window.
+    ///
+    /// Returns `false` if the device was lost (see [`Self::is_device_lost`]),
+    /// if the painter has no surface for the window, if the surface was
+    /// configured for a different size than the current size of the window (call
+    /// [`Self::on_window_resized`]), or if it was lost or outdated and has not been
+    /// configured again since.
+    pub fn surface_is_valid(&self, window: &winit::window::Window) -> bool {
+        if self.render_state.is_none() || self.is_device_lost() {
+            return false;
+        }
+        self.surfaces
+            .get(&window.id())
+            .map_or(false, |surface_state| {
+                let size = window.inner_size();
+                !surface_state.needs_reconfigure
+                    && size.width != 0
+                    && size.height != 0
+                    && (surface_state.width, surface_state.height) == (size.width, size.height)
+            })
+    }
+
+    /// Set the HiDPI scale factor (pixels per point) used when painting to the given window.
+    ///
+    /// This defaults to the scale factor of the window when it was passed to
+    /// [`Self::set_window`], and should be updated whenever the `pixels_per_point` of the
+    /// `egui::Context` painting to that window changes, e.g. when it is moved to a monitor with
+    /// a different DPI.
+    ///
+    /// # Errors
+    /// [`PainterError::NotInitialized`] if there is no surface for the window.
+    pub fn set_pixels_per_point(
+        &mut self,
+        window_id: WindowId,
+        pixels_per_point: f32,
+    ) -> Result<(), PainterError> {
+        let surface_state = self
+            .surfaces
+            .get_mut(&window_id)
+            .ok_or(PainterError::NotInitialized)?;
+        surface_state.pixels_per_point = pixels_per_point;
+        Ok(())
+    }
+
+    /// Use the given depth texture as the depth attachment of the egui render pass
+    /// for the given window.
+    ///
+    /// This lets [`crate::CallbackFn`]s depth-test their geometry against a depth buffer
+    /// that was filled outside of egui, e.g. by a 3D scene rendered before egui.
+    /// The depth texture is loaded rather than cleared at the start of the egui render pass.
+    ///
+    /// The texture must have the same size as the surface and the MSAA sample count of the
+    /// painter. It is not resized by the painter, so call this again after the window has been
+    /// resized.
+    ///
+    /// The format must match [`Self::depth_format`], since it is baked into the render pipeline.
+    /// To use a depth texture with a painter created without `depth_bits`, or with another
+    /// format, call [`Self::set_depth_format`] before [`Self::set_window`].
+    ///
+    /// # Errors
+    /// [`PainterError::NotInitialized`] if there is no surface for the window, or
+    /// [`PainterError::UnsupportedFormat`] if the format does not match the render pipeline.
+    pub fn set_depth_texture(
+        &mut self,
+        window_id: WindowId,
+        depth_texture_view: wgpu::TextureView,
+        depth_format: wgpu::TextureFormat,
+    ) -> Result<(), PainterError> {
+        if self.depth_format != Some(depth_format) {
+            return Err(PainterError::UnsupportedFormat(depth_format));
+        }
+        let surface_state = self
+            .surfaces
+            .get_mut(&window_id)
+            .ok_or(PainterError::NotInitialized)?;
+        surface_state.depth_texture_view = Some(depth_texture_view);
+        surface_state.external_depth_texture = true;
+        Ok(())
+    }
+
+    fn resize_and_generate_depth_texture_view(
+        &mut self,
+        window_id: WindowId,
+        width_in_pixels: u32,
+        height_in_pixels: u32,
+    ) {
+        crate::profile_function!();
+
+        let render_state = self
+            .render_state
+            .as_ref()
+            .expect("Render state should exist before surface configuration");
+        let surface_state = self
+            .surfaces
+            .get_mut(&window_id)
+            .expect("Surface state should exist before surface configuration");
+
+        configure_surface(
+            surface_state,
+            render_state,
+            &self.surface_configuration,
+            width_in_pixels,
+            height_in_pixels,
+        );
+
+        let device = &render_state.device;
+        let size = wgpu::Extent3d {
+            width: width_in_pixels,
+            height: height_in_pixels,
+            depth_or_array_layers: 1,
+        };
+        // Allocate the new MSAA texture before the old one is dropped.
+        let msaa_texture_view = (self.msaa_samples > 1).then(|| {
+            device
+                .create_texture(&wgpu::TextureDescriptor {
+                    label: Some(&format!("{}egui_msaa_texture", self.label_prefix)),
+                    size,
+                    mip_level_count: 1,
+                    sample_count: self.msaa_samples,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: render_state.target_format,
+                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                })
+                .create_view(&wgpu::TextureViewDescriptor::default())
+        });
+        surface_state.msaa_texture_view = msaa_texture_view;
+
+        if surface_state.external_depth_texture {
+            return;
+        }
+        surface_state.depth_texture_view = self.depth_format.map(|depth_format| {
+            device
+                .create_texture(&wgpu::TextureDescriptor {
+                    label: Some(&format!("{}egui_depth_texture", self.label_prefix)),
+                    size,
+                    mip_level_count: 1,
+                    sample_count: self.msaa_samples,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: depth_format,
+                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                        | wgpu::TextureUsages::TEXTURE_BINDING,
+                })
+                .create_view(&wgpu::TextureViewDescriptor::default())
+        });
+    }
+
+    /// Resize the surface (and depth texture) of the given window.
+    ///
+    /// # Errors
+    /// [`PainterError::NotInitialized`] if there is no surface for the window.
+    pub fn on_window_resized(
+        &mut self,
+        window_id: WindowId,
+        width_in_pixels: u32,
+        height_in_pixels: u32,
+    ) -> Result<(), PainterError> {
+        if !self.surfaces.contains_key(&window_id) {
+            return Err(PainterError::NotInitialized);
+        }
+        self.resize_and_generate_depth_texture_view(window_id, width_in_pixels, height_in_pixels);
+        Ok(())
+    }
+
+    /// Route a [`winit::event::WindowEvent`] from a shared event loop to the surface of the
+    /// window it was sent to.
+    ///
+    /// `Resized` and `ScaleFactorChanged` are handled with [`Self::surface_changed`], using the
+    /// current `pixels_per_point` of the window for `Resized`. Other events are ignored:
+    /// passing them on to the `egui::Context` of the window is up to the integration, e.g. with
+    /// `egui_winit::State::on_event`.
+    ///
+    /// Returns `true` if the window should be repainted, i.e. if its surface was reconfigured or
+    /// its scale factor changed.
+    pub fn handle_window_event(
+        &mut self,
+        window_id: WindowId,
+        event: &winit::event::WindowEvent<'_>,
+    ) -> bool {
+        match event {
+            winit::event::WindowEvent::Resized(size) => {
+                let pixels_per_point = if let Some(surface_state) = self.surfaces.get(&window_id) {
+                    surface_state.pixels_per_point
+                } else {
+                    return false;
+                };
+                self.surface_changed(window_id, size.width, size.height, pixels_per_point as f64)
+            }
+            winit::event::WindowEvent::ScaleFactorChanged {
+                scale_factor,
+                new_inner_size,
+            } => {
+                let reconfigured = self.surface_changed(
+                    window_id,
+                    new_inner_size.width,
+                    new_inner_size.height,
+                    *scale_factor,
+                );
+                // The scale factor changed, so a repaint is needed even if the size didn't.
+                reconfigured || self.surfaces.contains_key(&window_id)
+            }
+            _ => false,
+        }
+    }
+
+    /// Handle a winit `Resized` or `ScaleFactorChanged` event of the given window.
+    ///
+    /// Updates the `pixels_per_point` of the window to `scale_factor` (see
+    /// [`Self::set_pixels_per_point`]), and reconfigures its surface (and depth texture) if the
+    /// physical size changed, or the surface was lost or outdated.
+    /// Zero-sized windows, e.g. minimized ones, are not reconfigured.
+    ///
+    /// Returns `true` if the surface was reconfigured, in which case the window should be
+    /// repainted. Returns `false` if there is no surface for the window.
+    pub fn surface_changed(
+        &mut self,
+        window_id: WindowId,
+        width_in_pixels: u32,
+        height_in_pixels: u32,
+        scale_factor: f64,
+    ) -> bool {
+        let surface_state = if let Some(surface_state) = self.surfaces.get_mut(&window_id) {
+            surface_state
+        } else {
+            return false;
+        };
+
+        if is_valid_pixels_per_point(scale_factor as f32) {
+            surface_state.pixels_per_point = scale_factor as f32;
+        }
+
+        let size_changed =
+            (surface_state.width, surface_state.height) != (width_in_pixels, height_in_pixels);
+        let needs_reconfigure = (size_changed || surface_state.needs_reconfigure)
+            && width_in_pixels != 0
+            && height_in_pixels != 0;
+        if needs_reconfigure {
+            self.resize_and_generate_depth_texture_view(
+                window_id,
+                width_in_pixels,
+                height_in_pixels,
+            );
+        }
+        needs_reconfigure
+    }
+
+    /// Paints egui to the window with the given [`WindowId`], using the `pixels_per_point`
+    /// of that window (see [`Self::set_pixels_per_point`]).
+    ///
+    /// `textures_deltas` may hold the deltas of several egui contexts, e.g. one per window.
+    /// All of them are uploaded before painting, and their textures are freed afterwards.
+    ///
+    /// Textures that fail to upload are logged and skipped, rather than failing the whole frame.
+    ///
+    /// With a `clear_color` the surface is cleared before egui is painted. With `None` egui is
+    /// painted on top of the contents of the surface texture, which are undefined for a newly
+    /// acquired texture on most platforms. With MSAA, it is painted on top of the previous frame
+    /// in the multisampled texture instead.
+    ///
+    /// If acquiring the surface texture times out, it is retried a few times with an increasing
+    /// delay. If it keeps timing out the frame is skipped, and an empty [`FrameStats`] is returned.
+    /// A lost surface is configured again once. If it is still lost, it is handled like any other
+    /// surface error: it is passed to [`WgpuConfiguration::on_surface_error`], the surface is
+    /// marked for reconfiguration, and [`PainterError::SurfaceError`] is returned.
+    ///
+    /// # Errors
+    /// If the painter has no surface for the window, its `pixels_per_point` is invalid,
+    /// the surface texture could not be acquired, or the device was lost
+    /// (see [`Self::is_device_lost`]). In all cases nothing is painted.
+    pub fn paint_and_update_textures(
+        &mut self,
+        window_id: WindowId,
+        clear_color: Option<epaint::Rgba>,
+        clipped_primitives: &[epaint::ClippedPrimitive],
+        textures_deltas: impl AsRef<[epaint::textures::TexturesDelta]>,
+    ) -> Result<FrameStats, PainterError> {
+        crate::profile_function!();
+
+        if self.is_device_lost() {
+            return Err(PainterError::DeviceLost);
+        }
+        let render_state = self
+            .render_state
+            .as_mut()
+            .ok_or(PainterError::NotInitialized)?;
+        let surface_state = self
+            .surfaces
+            .get_mut(&window_id)
+            .ok_or(PainterError::NotInitialized)?;
+        if !is_valid_pixels_per_point(surface_state.pixels_per_point) {
+            return Err(PainterError::InvalidPixelsPerPoint(
+                surface_state.pixels_per_point,
+            ));
+        }
+        let (width, height) = (surface_state.width, surface_state.height);
+
+        if let Some(frame) = surface_state.current_frame.take() {
+            crate::profile_scope!("present");
+            frame.present();
+        }
+
+        let output_frame = {
+            crate::profile_scope!("get_current_texture");
+            // This is what vsync-waiting happens, at least on Mac.
+            let mut output_frame = surface_state.surface.get_current_texture();
+
+            // A timeout happens on some platforms when the GPU is overloaded, so give it some time.
+            let mut backoff = SURFACE_TIMEOUT_INITIAL_BACKOFF;
+            for _ in 0..SURFACE_TIMEOUT_RETRIES {
+                if output_frame.as_ref().err() != Some(&wgpu::SurfaceError::Timeout) {
+                    break;
+                }
+                std::thread::sleep(backoff);
+                backoff *= 2;
+                output_frame = surface_state.surface.get_current_texture();
+            }
+
+            // A lost surface can usually be recovered by configuring it again.
+            if output_frame.as_ref().err() == Some(&wgpu::SurfaceError::Lost) {
+                configure_surface(
+                    surface_state,
+                    render_state,
+                    &self.surface_configuration,
+                    width,
+                    height,
+                );
+                output_frame = surface_state.surface.get_current_texture();
+            }
+            output_frame
+        };
+
+        let output_frame = match output_frame {
+            Ok(frame) => frame,
+            Err(wgpu::SurfaceError::Timeout) => {
+                warn!(
+                    "Skipping frame: timed out acquiring the surface texture {} times",
+                    SURFACE_TIMEOUT_RETRIES + 1
+                );
+                self.update_textures_without_painting(textures_deltas.as_ref());
+                return Ok(FrameStats::default());
+            }
+            Err(e) => {
+                surface_state.needs_reconfigure |=
+                    matches!(e, wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated);
+                if matches!(
+                    (*self.configuration.on_surface_error)(e.clone()),
+                    SurfaceErrorAction::RecreateSurface
+                ) {
+                    configure_surface(
+                        surface_state,
+                        render_state,
+                        &self.surface_configuration,
+                        width,
+                        height,
+                    );
+                }
+                self.update_textures_without_painting(textures_deltas.as_ref());
+                return Err(PainterError::SurfaceError(e));
+            }
+        };
+
+        let frame_view = output_frame
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        let frame_stats = self.paint_to_target(
+            PaintTarget::Surface {
+                window_id,
+                view: &frame_view,
+            },
+            clear_color,
+            clipped_primitives,
+            textures_deltas.as_ref(),
+        )?;
+
+        if self.manual_present {
+            if let Some(surface_state) = self.surfaces.get_mut(&window_id) {
+                surface_state.current_frame = Some(output_frame);
+            }
+        } else {
+            // Redraw egui
+            crate::profile_scope!("present");
+            output_frame.present();
+        }
+
+        Ok(frame_stats)
+    }
+
+    /// Paints egui to the offscreen render target of a painter created with
+    /// [`Self::new_with_format`].
+    ///
+    /// The result can be accessed with [`Self::offscreen_texture`]. With a `clear_color` the
+    /// target is cleared first, otherwise egui is painted over its contents, e.g. a 3D view the
+    /// application rendered into it.
+    ///
+    /// # Errors
+    /// [`PainterError::NotInitialized`] for a painter without an offscreen render target,
+    /// [`PainterError::DeviceLost`], or [`PainterError::InvalidPixelsPerPoint`].
+    /// In all cases nothing is painted.
+    pub fn paint_offscreen_and_update_textures(
+        &mut self,
+        pixels_per_point: f32,
+        clear_color: Option<epaint::Rgba>,
+        clipped_primitives: &[epaint::ClippedPrimitive],
+        textures_deltas: impl AsRef<[epaint::textures::TexturesDelta]>,
+    ) -> Result<FrameStats, PainterError> {
+        crate::profile_function!();
+
+        if self.offscreen_target.is_none() {
+            return Err(PainterError::NotInitialized);
+        }
+        if self.is_device_lost() {
+            return Err(PainterError::DeviceLost);
+        }
+        if !is_valid_pixels_per_point(pixels_per_point) {
+            return Err(PainterError::InvalidPixelsPerPoint(pixels_per_point));
+        }
+
+        self.paint_to_target(
+            PaintTarget::Offscreen { pixels_per_point },
+            clear_color,
+            clipped_primitives,
+            textures_deltas.as_ref(),
+        )
+    }
+
+    /// Paints egui to the offscreen render target of a painter created with
+    /// [`Self::new_with_format`], and reads back the result.
+    ///
+    /// This runs the whole render pipeline without a window, e.g. for screenshot tests in CI.
+    /// It blocks until the GPU is done, and submits the frame even in
+    /// [`SubmissionMode::Deferred`].
+    ///
+    /// # Errors
+    /// Those of [`Self::paint_offscreen_and_update_textures`],
+    /// [`PainterError::UnsupportedFormat`] if the format of the offscreen render target is not
+    /// one of `Rgba8Unorm`, `Rgba8UnormSrgb`, `Bgra8Unorm` or `Bgra8UnormSrgb`,
+    /// or [`PainterError::Readback`].
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn paint_headless(
+        &mut self,
+        pixels_per_point: f32,
+        clear_color: Option<epaint::Rgba>,
+        clipped_primitives: &[epaint::ClippedPrimitive],
+        textures_deltas: impl AsRef<[epaint::textures::TexturesDelta]>,
+    ) -> Result<epaint::ColorImage, PainterError> {
+        crate::profile_function!();
+
+        if let Some(render_state) = &self.render_state {
+            if !crate::readback::is_readable_format(render_state.target_format) {
+                return Err(PainterError::UnsupportedFormat(render_state.target_format));
+            }
+        }
+        self.paint_offscreen_and_update_textures(
+            pixels_per_point,
+            clear_color,
+            clipped_primitives,
+            textures_deltas,
+        )?;
+
+        let (render_state, offscreen_target) = match (&self.render_state, &self.offscreen_target) {
+            (Some(render_state), Some(offscreen_target)) => (render_state, offscreen_target),
+            _ => return Err(PainterError::NotInitialized),
+        };
+        if let Some(command_buffer) = self.deferred_command_buffer.take() {
+            render_state.queue.submit(std::iter::once(command_buffer));
+        }
+        crate::readback::read_texture(
+            &render_state.device,
+            &render_state.queue,
+            &offscreen_target.texture,
+            render_state.target_format,
+            [offscreen_target.width, offscreen_target.height],
+        )
+        .map_err(PainterError::Readback)
+    }
+
+    /// Download a texture managed by egui, such as the font atlas, from the GPU.
+    ///
+    /// Useful for debugging, e.g. to check in CI that the font atlas is packed correctly.
+    /// Returns `None` if there is no such texture, or if downloading it failed.
+    /// See [`Renderer::read_texture`].
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn dump_texture_atlas(&self, id: epaint::TextureId) -> Option<epaint::ColorImage> {
+        crate::profile_function!();
+
+        let render_state = self.render_state.as_ref()?;
+        let result = render_state.renderer.read().read_texture(
+            &render_state.device,
+            &render_state.queue,
+            &id,
+        )?;
+        result
+            .map_err(|err| error!("Failed to read back texture {:?}: {}", id, err))
+            .ok()
+    }
+
+    /// Apply the textures deltas of a frame that is skipped, since egui won't send them again.
+    ///
+    /// Texture uploads are only executed with the next submission, so this submits right away
+    /// instead of leaving them in the queue until the next painted frame.
+    fn update_textures_without_painting(
+        &mut self,
+        textures_deltas: &[epaint::textures::TexturesDelta],
+    ) {
+        crate::profile_function!();
+
+        let render_state = if let Some(render_state) = &self.render_state {
+            render_state
+        } else {
+            return;
+        };
+        let mut renderer = render_state.renderer.write();
+
+        update_textures(
+            &mut renderer,
+            render_state,
+            textures_deltas,
+            self.texture_updates_enabled,
+        );
+        self.texture_updates_enabled = true;
+        drop(renderer);
+
+        render_state.queue.submit(std::iter::empty());
+        free_textures(render_state, textures_deltas);
+    }
+
+    /// Uploads the textures and geometry, and records and submits the egui render pass
+    /// for the given target.
+    fn paint_to_target(
+        &mut self,
+        target: PaintTarget<'_>,
+        clear_color: Option<epaint::Rgba>,
+        clipped_primitives: &[epaint::ClippedPrimitive],
+        textures_deltas: &[epaint::textures::TexturesDelta],
+    ) -> Result<FrameStats, PainterError> {
+        debug_assert!(
+            self.deferred_command_buffer.is_none(),
+            "The deferred egui command buffer must be taken and submitted before painting again, \
+             since painting overwrites the buffers it reads"
+        );
+
+        let render_state = self
+            .render_state
+            .as_ref()
+            .ok_or(PainterError::NotInitialized)?;
+
+        let deferred = matches!(target, PaintTarget::Offscreen { .. })
+            && self.submission_mode == SubmissionMode::Deferred;
+
+        let (
+            (color_view, resolve_target),
+            depth_texture_view,
+            load_depth,
+            size_in_pixels,
+            pixels_per_point,
+        ) = match target {
+            PaintTarget::Surface { window_id, view } => {
+                let surface_state = &self.surfaces[&window_id];
+                (
+                    match &surface_state.msaa_texture_view {
+                        Some(msaa_texture_view) => (msaa_texture_view, Some(view)),
+                        None => (view, None),
+                    },
+                    surface_state.depth_texture_view.as_ref(),
+                    surface_state.external_depth_texture,
+                    [surface_state.width, surface_state.height],
+                    surface_state.pixels_per_point,
+                )
+            }
+            PaintTarget::Offscreen { pixels_per_point } => {
+                let offscreen_target = self
+                    .offscreen_target
+                    .as_ref()
+                    .expect("Offscreen target should exist before offscreen painting");
+                (
+                    (&offscreen_target.view, None),
+                    None,
+                    false,
+                    [offscreen_target.width, offscreen_target.height],
+                    pixels_per_point,
+                )
+            }
+        };
+
+        let mut encoder =
+            render_state
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some(&format!(
+                        "{}{}",
+                        self.label_prefix, self.frame_encoder_label
+                    )),
+                });
+
+        let gpu_timer_queries = if deferred {
+            // The readback buffers are mapped right after submitting, which we don't control here.
+            None
+        } else {
+            self.gpu_timer.as_mut().and_then(GpuTimer::begin_frame)
+        };
+        if let (Some(gpu_timer), Some(queries)) = (&self.gpu_timer, gpu_timer_queries) {
+            gpu_timer.write_timestamp(&mut encoder, queries, 0);
+        }
+
+        let (target_origin, size_in_pixels) = match self.target_region {
+            // The target may have shrunk since the region was set.
+            Some(region) => clamp_region(region, size_in_pixels),
+            None => ([0, 0], size_in_pixels),
+        };
+
+        // Upload all resources for the GPU.
+        let screen_descriptor = renderer::ScreenDescriptor {
+            size_in_pixels,
+            pixels_per_point,
+        };
+
+        // The renderer is locked once for recording the whole frame, since painting always
+        // happens on the same thread and the lock is uncontended.
+        let mut renderer = render_state.renderer.write();
+        renderer.set_scissor_clamp(self.scissor_clamp);
+        renderer.set_target_origin(target_origin);
+
+        update_textures(
+            &mut renderer,
+            render_state,
+            textures_deltas,
+            self.texture_updates_enabled,
+        );
+        self.texture_updates_enabled = true;
+
+        let update_buffers_result = renderer.update_buffers(
+            &render_state.device,
+            &render_state.queue,
+            &mut encoder,
+            clipped_primitives,
+            &screen_descriptor,
+        );
+
+        if let (Some(gpu_timer), Some(queries)) = (&self.gpu_timer, gpu_timer_queries) {
+            gpu_timer.write_timestamp(&mut encoder, queries, 1);
+        }
+
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: color_view,
+                    resolve_target,
+                    ops: color_attachment_ops(clear_color),
+                })],
+                depth_stencil_attachment: depth_texture_view.map(|view| {
+                    wgpu::RenderPassDepthStencilAttachment {
+                        view,
+                        depth_ops: Some(wgpu::Operations {
+                            load: if load_depth {
+                                wgpu::LoadOp::Load
+                            } else {
+                                wgpu::LoadOp::Clear(1.0)
+                            },
+                            store: true,
+                        }),
+                        stencil_ops: self
+                            .depth_format
+                            .map_or(false, has_stencil_aspect)
+                            .then_some(wgpu::Operations {
+                                load: if load_depth {
+                                    wgpu::LoadOp::Load
+                                } else {
+                                    wgpu::LoadOp::Clear(0)
+                                },
+                                store: true,
+                            }),
+                    }
+                }),
+                label: Some(&format!("{}egui_render", self.label_prefix)),
+            });
+
+            // wgpu rejects empty viewports, so only clear the target if the region is empty.
+            if size_in_pixels[0] > 0 && size_in_pixels[1] > 0 {
+                renderer.render(&mut render_pass, clipped_primitives, &screen_descriptor);
+            }
+        }
+
+        if let (Some(gpu_timer), Some(queries)) = (&mut self.gpu_timer, gpu_timer_queries) {
+            gpu_timer.write_timestamp(&mut encoder, queries, 2);
+            gpu_timer.resolve(&mut encoder, queries);
+        }
+        drop(renderer);
+
+        let encoded = {
+            crate::profile_scope!("CommandEncoder::finish");
+            encoder.finish()
+        };
+
+        if deferred {
+            // The user-defined buffers are submitted right away, so they are executed before ours.
+            if !update_buffers_result.command_buffers.is_empty() {
+                crate::profile_scope!("Queue::submit");
+                render_state
+                    .queue
+                    .submit(update_buffers_result.command_buffers);
+            }
+            if self.deferred_command_buffer.replace(encoded).is_some() {
+                warn!("Dropping an egui command buffer that was never taken with Painter::take_command_buffer");
+            }
+        } else {
+            // Submit the commands: both the main buffer and user-defined ones.
+            {
+                crate::profile_scope!("Queue::submit");
+                render_state.queue.submit(
+                    update_buffers_result
+                        .command_buffers
+                        .into_iter()
+                        .chain(std::iter::once(encoded)),
+                );
+            };
+
+            if let Some(gpu_timer) = &mut self.gpu_timer {
+                gpu_timer.after_submit(&render_state.device);
+            }
+        }
+
+        free_textures(render_state, textures_deltas);
+
+        Ok(FrameStats {
+            render_stage_timings: self.gpu_timer.as_ref().and_then(GpuTimer::latest),
+            vertex_bytes: update_buffers_result.vertex_bytes,
+            index_bytes: update_buffers_result.index_bytes,
+            texture_bytes: update_buffers_result.texture_bytes,
+            vertex_buffer_bytes: update_buffers_result.vertex_buffer_bytes,
+            index_buffer_bytes: update_buffers_result.index_buffer_bytes,
+            uniform_buffer_bytes: update_buffers_result.uniform_buffer_bytes,
+        })
+    }
+
+    /// Release all GPU resources held by the painter.
+    ///
+    /// Waits for the GPU to finish all submitted work before returning,
+    /// so that e.g. Metal objects are released before the window's `CAMetalLayer` is destroyed.
+    ///
+    /// The renderer may still be referenced by clones of the [`RenderState`],
+    /// so its textures and paint callback resources are freed explicitly.
+    pub fn destroy(&mut self) {
+        crate::profile_function!();
+
+        self.gpu_timer = None;
+        self.deferred_command_buffer = None;
+        self.offscreen_target = None;
+        self.surfaces.clear();
+
+        if let Some(render_state) = self.render_state.take() {
+            render_state.renderer.write().free_all_resources();
+            render_state.device.poll(wgpu::Maintain::Wait);
+        }
+    }
+}
+
+/// Free the textures of the given deltas.
+///
+/// Called after the frame has been recorded, since its meshes may still use them.
+/// This is fine even before the frame is submitted, see [`Renderer::free_texture`].
+fn free_textures(render_state: &RenderState, textures_deltas: &[epaint::textures::TexturesDelta]) {
+    let mut freed = textures_deltas
+        .iter()
+        .flat_map(|delta| &delta.free)
+        .peekable();
+    if freed.peek().is_none() {
+        return;
+    }
+    let mut renderer = render_state.renderer.write();
+    for id in freed {
+        renderer.free_texture(id);
+    }
+}
+
+/// Upload the textures set by the given deltas, logging the ones that fail.
+///
+/// Without `updates_enabled`, only textures the renderer does not have yet are uploaded.
+/// See [`Painter::set_texture_updates_enabled`].
+fn update_textures(
+    renderer: &mut Renderer,
+    render_state: &RenderState,
+    textures_deltas: &[epaint::textures::TexturesDelta],
+    updates_enabled: bool,
+) {
+    let texture_sets = textures_deltas.iter().flat_map(|delta| &delta.set);
+    for (id, image_delta) in texture_sets {
+        if !updates_enabled && renderer.texture(id).is_some() {
+            continue;
+        }
+        if let Err(err) =
+            renderer.update_texture(&render_state.device, &render_state.queue, *id, image_delta)
+        {
+            error!("Failed to update texture {:?}: {}", id, err);
+        }
+    }
+}
+
+/// The origin and size of the part of the `[x, y, width, height]` region that lies within a
+/// target of the given size.
+fn clamp_region([x, y, width, height]: [u32; 4], target_size: [u32; 2]) -> ([u32; 2], [u32; 2]) {
+    let x = x.min(target_size[0]);
+    let y = y.min(target_size[1]);
+    (
+        [x, y],
+        [
+            width.min(target_size[0] - x),
+            height.min(target_size[1] - y),
+        ],
+    )
+}
+
+/// The initial `surface_configuration` of a [`Painter`]. The format and size are placeholders,
+/// which are replaced whenever a surface is configured.
+fn default_surface_configuration(present_mode: wgpu::PresentMode) -> wgpu::SurfaceConfiguration {
+    wgpu::SurfaceConfiguration {
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        format: wgpu::TextureFormat::Bgra8Unorm,
+        width: 0,
+        height: 0,
+        present_mode,
+        alpha_mode: wgpu::CompositeAlphaMode::Auto,
+    }
+}
+
+fn configure_surface(
+    surface_state: &mut SurfaceState,
+    render_state: &RenderState,
+    surface_configuration: &wgpu::SurfaceConfiguration,
+    width_in_pixels: u32,
+    height_in_pixels: u32,
+) {
+    crate::profile_function!();
+
+    // The surface can't be configured while one of its textures is acquired.
+    surface_state.current_frame = None;
+
+    let config = wgpu::SurfaceConfiguration {
+        format: render_state.target_format,
+        width: width_in_pixels,
+        height: height_in_pixels,
+        ..surface_configuration.clone()
+    };
+
+    surface_state
+        .surface
+        .configure(&render_state.device, &config);
+    surface_state.width = width_in_pixels;
+    surface_state.height = height_in_pixels;
+    surface_state.needs_reconfigure = false;
+}
+
+/// Sets `device_lost` when wgpu reports an error caused by `device` having been lost.
+fn watch_device_lost(device: &wgpu::Device, device_lost: Arc<AtomicBool>) {
+    device.on_uncaptured_error(move |err| {
+        if is_device_lost_error(&err) {
+            error!("The wgpu device was lost: {}", err);
+            device_lost.store(true, Ordering::Relaxed);
+        } else {
+            // What the default handler of wgpu does.
+            panic!("wgpu error: {}\n", err);
+        }
+    });
+}
+
+/// wgpu 0.14 has no device lost callback, and does not expose the `wgpu_core` error type.
+/// It does report a lost device in the source chain of every error it causes though.
+fn is_device_lost_error(err: &wgpu::Error) -> bool {
+    let mut source: Option<&(dyn std::error::Error + 'static)> = Some(err);
+    while let Some(err) = source {
+        if err.to_string() == "parent device is lost" {
+            return true;
+        }
+        source = err.source();
+    }
+    false
+}
+
+/// A `pixels_per_point` of zero, or one that is negative or not finite,
+/// would lead to a division by zero and invalid scissor rectangles when painting.
+fn is_valid_pixels_per_point(pixels_per_point: f32) -> bool {
+    pixels_per_point > 0.0 && pixels_per_point.is_finite()
+}
+
+/// The present mode wgpu configures a surface with when `requested` is, given the modes the
+/// surface supports, or `None` if `requested` is not supported.
+fn resolve_present_mode(
+    requested: wgpu::PresentMode,
+    supported: &[wgpu::PresentMode],
+) -> Option<wgpu::PresentMode> {
+    use wgpu::PresentMode;
+
+    // The same fallbacks as in `wgpu_core`.
+    let fallbacks: &[PresentMode] = match requested {
+        PresentMode::AutoVsync => &[PresentMode::FifoRelaxed, PresentMode::Fifo],
+        PresentMode::AutoNoVsync => &[
+            PresentMode::Immediate,
+            PresentMode::Mailbox,
+            PresentMode::Fifo,
+        ],
+        _ => &[],
+    };
+    std::iter::once(&requested)
+        .chain(fallbacks)
+        .find(|mode| supported.contains(mode))
+        .copied()
+}
+
+fn has_stencil_aspect(format: wgpu::TextureFormat) -> bool {
+    matches!(
+        format,
+        wgpu::TextureFormat::Depth24PlusStencil8 | wgpu::TextureFormat::Depth32FloatStencil8
+    )
+}
+
+/// The operations for the color attachment of the egui render pass.
+///
+/// With a `clear_color` the target is cleared before egui is painted,
+/// otherwise egui is painted on top of whatever is already in the target.
+/// The result is always stored.
+fn color_attachment_ops(clear_color: Option<epaint::Rgba>) -> wgpu::Operations<wgpu::Color> {
+    let load = match clear_color {
+        Some(clear_color) => wgpu::LoadOp::Clear(wgpu::Color {
+            r: clear_color.r() as f64,
+            g: clear_color.g() as f64,
+            b: clear_color.b() as f64,
+            a: clear_color.a() as f64,
+        }),
+        None => wgpu::LoadOp::Load,
+    };
+    wgpu::Operations { load, store: true }
+}
+
+#[test]
+fn color_attachment_ops_store_op_is_store() {
+    assert!(color_attachment_ops(None).store);
+    assert!(color_attachment_ops(Some(epaint::Rgba::WHITE)).store);
+}
+
+#[test]
+fn color_attachment_ops_without_clear_color_loads() {
+    assert_eq!(color_attachment_ops(None).load, wgpu::LoadOp::Load);
+}
+
+#[test]
+fn color_attachment_ops_with_clear_color_clears() {
+    assert_eq!(
+        color_attachment_ops(Some(epaint::Rgba::TRANSPARENT)).load,
+        wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT)
+    );
+}
+
+#[test]
+fn device_lost_errors_are_detected() {
+    #[derive(Debug)]
+    struct Cause(&'static str);
+
+    impl std::fmt::Display for Cause {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.write_str(self.0)
+        }
+    }
+
+    impl std::error::Error for Cause {}
+
+    let validation = |cause| wgpu::Error::Validation {
+        source: Box::new(Cause(cause)),
+        description: String::new(),
+    };
+    assert!(is_device_lost_error(&validation("parent device is lost")));
+    assert!(!is_device_lost_error(&validation(
+        "parent device is invalid"
+    )));
+}
+
+#[test]
+fn validate_pixels_per_point() {
+    assert!(is_valid_pixels_per_point(1.0));
+    assert!(is_valid_pixels_per_point(0.5));
+    assert!(!is_valid_pixels_per_point(0.0));
+    assert!(!is_valid_pixels_per_point(-1.0));
+    assert!(!is_valid_pixels_per_point(f32::NAN));
+    assert!(!is_valid_pixels_per_point(f32::INFINITY));
+}
+
+#[test]
+fn resolve_auto_present_modes() {
+    use wgpu::PresentMode;
+
+    let supported = [PresentMode::Fifo, PresentMode::Mailbox];
+    assert_eq!(
+        resolve_present_mode(PresentMode::AutoVsync, &supported),
+        Some(PresentMode::Fifo)
+    );
+    assert_eq!(
+        resolve_present_mode(PresentMode::AutoNoVsync, &supported),
+        Some(PresentMode::Mailbox)
+    );
+    assert_eq!(
+        resolve_present_mode(PresentMode::Mailbox, &supported),
+        Some(PresentMode::Mailbox)
+    );
+    assert_eq!(
+        resolve_present_mode(PresentMode::Immediate, &supported),
+        None
+    );
+}
+
+#[test]
+fn target_region_is_clamped_to_target() {
+    assert_eq!(
+        clamp_region([10, 20, 100, 50], [200, 100]),
+        ([10, 20], [100, 50])
+    );
+    assert_eq!(
+        clamp_region([10, 20, 100, 50], [50, 40]),
+        ([10, 20], [40, 20])
+    );
+    assert_eq!(
+        clamp_region([60, 20, 100, 50], [50, 40]),
+        ([50, 20], [0, 20])
+    );
+}
+
+#[test]
+fn swap_render_state_replaces_instance_and_adapter() {
+    let (instance, adapter, device, queue) = match crate::test_device() {
+        Some(test_device) => test_device,
+        None => return,
+    };
+    let format = wgpu::TextureFormat::Rgba8Unorm;
+
+    // The painter does not know where its device came from.
+    let mut painter = Painter::new_with_format(device.clone(), queue, format, 4, 4).unwrap();
+    assert!(painter.adapter.is_none());
+    assert!(painter.instance.is_none());
+
+    let adapter_info = adapter.get_info();
+    let render_state = RenderState {
+        renderer: Arc::new(RwLock::new(Renderer::new(
+            &device,
+            &RendererDescriptor {
+                output_color_format: format,
+                ..Default::default()
+            },
+        ))),
+        ..painter.render_state().unwrap()
+    };
+    let old_render_state = painter
+        .swap_render_state(instance.clone(), adapter, render_state.clone())
+        .unwrap();
+
+    assert!(Arc::ptr_eq(painter.instance.as_ref().unwrap(), &instance));
+    assert_eq!(
+        painter.adapter.as_ref().map(Adapter::get_info),
+        Some(adapter_info)
+    );
+    let new_render_state = painter.render_state().unwrap();
+    assert!(Arc::ptr_eq(
+        &new_render_state.renderer,
+        &render_state.renderer
+    ));
+    assert!(!Arc::ptr_eq(
+        &new_render_state.renderer,
+        &old_render_state.renderer
+    ));
+
+    // Painting continues with the new renderer.
+    painter
+        .paint_offscreen_and_update_textures(1.0, Some(epaint::Rgba::BLACK), &[], [])
+        .unwrap();
+}
+
+#[test]
+fn disabled_texture_updates_only_upload_new_textures() {
+    use epaint::{textures::TexturesDelta, Color32, ColorImage, ImageDelta, TextureId};
+
+    let (_, _, device, queue) = match crate::test_device() {
+        Some(test_device) => test_device,
+        None => return,
+    };
+    let renderer = Renderer::new(&device, &RendererDescriptor::default());
+    let render_state = RenderState {
+        max_texture_side: device.limits().max_texture_dimension_2d as usize,
+        device,
+        queue,
+        target_format: RendererDescriptor::default().output_color_format,
+        renderer: Arc::new(RwLock::new(renderer)),
+    };
+    let delta = |color| {
+        let mut delta = TexturesDelta::default();
+        let image = ColorImage::new([1, 1], color);
+        delta.set.push((
+            TextureId::Managed(0),
+            ImageDelta::full(image, Default::default()),
+        ));
+        vec![delta]
+    };
+    let update = |color, updates_enabled| {
+        let mut renderer = render_state.renderer.write();
+        update_textures(&mut renderer, &render_state, &delta(color), updates_enabled);
+    };
+    let read_texture = || {
+        render_state
+            .renderer
+            .read()
+            .read_texture(
+                &render_state.device,
+                &render_state.queue,
+                &TextureId::Managed(0),
+            )
+            .unwrap()
+            .unwrap()
+            .pixels
+    };
+
+    // A new texture is uploaded even with updates disabled.
+    update(Color32::RED, false);
+    assert_eq!(read_texture(), vec![Color32::RED]);
+
+    // An existing one is kept.
+    update(Color32::BLUE, false);
+    assert_eq!(read_texture(), vec![Color32::RED]);
+
+    update(Color32::BLUE, true);
+    assert_eq!(read_texture(), vec![Color32::BLUE]);
 }
@@ -377,6 +377,8 @@ impl Painter {
                             clip_rect: *clip_rect,
                             pixels_per_point,
                             screen_size_px,
+                            target_origin_px: [0, 0],
+                            scissor_rect: None,
                         };
 
                         if let Some(callback) = callback.callback.downcast_ref::<CallbackFn>() {
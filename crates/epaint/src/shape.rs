@@ -751,6 +751,18 @@ pub struct PaintCallbackInfo {
 
     /// Full size of the screen, in pixels.
     pub screen_size_px: [u32; 2],
+
+    /// Offset of the screen within the render target, in pixels.
+    ///
+    /// This is zero unless the backend paints egui into a sub-region of its target.
+    /// All other values, including [`Self::viewport_in_pixels`] and
+    /// [`Self::clip_rect_in_pixels`], are relative to that region, so add this offset to them
+    /// when setting a viewport or scissor rect on the render target.
+    pub target_origin_px: [u32; 2],
+
+    /// The scissor rectangle the backend has set before calling the callback, in points, if it
+    /// differs from the default computed by [`Self::current_scissor_rect`].
+    pub scissor_rect: Option<Rect>,
 }
 
 pub struct ViewportInPixels {
@@ -792,6 +804,55 @@ impl PaintCallbackInfo {
     pub fn clip_rect_in_pixels(&self) -> ViewportInPixels {
         self.points_to_pixels(&self.clip_rect)
     }
+
+    /// The scissor rectangle the backend has set before calling the callback, in points.
+    ///
+    /// This is [`Self::scissor_rect`] if the backend provided it. Otherwise it is
+    /// [`Self::clip_rect`] rounded to whole physical pixels and clamped to the screen,
+    /// i.e. the region the callback can actually paint to.
+    ///
+    /// Like [`Self::clip_rect`], it is relative to [`Self::target_origin_px`].
+    pub fn current_scissor_rect(&self) -> Rect {
+        if let Some(scissor_rect) = self.scissor_rect {
+            return scissor_rect;
+        }
+
+        let pixels_per_point = self.pixels_per_point;
+        let screen_size = vec2(self.screen_size_px[0] as f32, self.screen_size_px[1] as f32);
+        let min = (self.clip_rect.min.to_vec2() * pixels_per_point)
+            .round()
+            .clamp(Vec2::ZERO, screen_size);
+        let max = (self.clip_rect.max.to_vec2() * pixels_per_point)
+            .round()
+            .clamp(min, screen_size);
+        Rect::from_min_max(
+            (min / pixels_per_point).to_pos2(),
+            (max / pixels_per_point).to_pos2(),
+        )
+    }
+}
+
+#[test]
+fn current_scissor_rect_is_rounded_and_clamped() {
+    let info = PaintCallbackInfo {
+        viewport: Rect::EVERYTHING,
+        clip_rect: Rect::from_min_max(pos2(10.2, -5.0), pos2(30.7, 40.0)),
+        pixels_per_point: 2.0,
+        screen_size_px: [100, 50],
+        target_origin_px: [0, 0],
+        scissor_rect: None,
+    };
+    assert_eq!(
+        info.current_scissor_rect(),
+        Rect::from_min_max(pos2(10.0, 0.0), pos2(30.5, 25.0))
+    );
+
+    let scissor_rect = Rect::from_min_max(pos2(0.0, 0.0), pos2(60.0, 30.0));
+    let info = PaintCallbackInfo {
+        scissor_rect: Some(scissor_rect),
+        ..info
+    };
+    assert_eq!(info.current_scissor_rect(), scissor_rect);
 }
 
 /// If you want to paint some 3D shapes inside an egui region, you can use this.